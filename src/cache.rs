@@ -0,0 +1,350 @@
+use std::env::current_dir;
+use std::fs::{create_dir_all, read_dir, rename, File};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+
+use cargo::util::homedir;
+use cargo_util::Sha256;
+use failure::ResultExt;
+use flate2::read::GzDecoder;
+use rm_rf::remove as remove_dir_all;
+use tar::Archive;
+
+use crate::command::{Crate, DependencyTypeEnum};
+use crate::error::{ErrorKind, Result};
+use crate::util::copy_directory;
+
+// Directories of the crates.io index in the Cargo home: the sparse protocol
+// uses `index.crates.io-<hash>` and the git one `github.com-<hash>`.
+const CRATES_IO_DIRECTORIES: [&str; 2] = ["index.crates.io", "github.com"];
+
+// Crates that Cargo has already downloaded into `$CARGO_HOME/registry`:
+// `.crate` archives in `cache/` and their unpacked sources in `src/`.
+#[derive(Debug, Clone)]
+pub struct RegistryCache {
+    registry_directory: Option<PathBuf>,
+}
+
+impl RegistryCache {
+    pub fn load() -> Self {
+        let registry_directory = current_dir()
+            .ok()
+            .and_then(|cwd| homedir(&cwd))
+            .map(|home| home.join("registry"));
+
+        RegistryCache { registry_directory }
+    }
+
+    // Looks for the downloaded `.crate` archive. The archive is used only when
+    // its checksum matches the one from Cargo.lock (if the lockfile has it).
+    pub fn find_archive(&self, used_crate: &Crate) -> Option<PathBuf> {
+        let file_name = format!(
+            "{}-{}.crate",
+            used_crate.get_name(),
+            used_crate.get_version()
+        );
+        self.get_index_directories("cache", used_crate)
+            .into_iter()
+            .map(|directory| directory.join(&file_name))
+            .filter(|path| path.is_file())
+            .find(|path| match used_crate.get_checksum() {
+                Some(checksum) => Sha256::new()
+                    .update_path(path)
+                    .map(|hasher| hasher.finish_hex() == checksum)
+                    .unwrap_or(false),
+                None => true,
+            })
+    }
+
+    // Looks for the sources that Cargo has unpacked. Cargo creates the
+    // `.cargo-ok` file in the end, so partially unpacked crates are skipped.
+    pub fn find_sources(&self, used_crate: &Crate) -> Option<PathBuf> {
+        let directory_name = format!("{}-{}", used_crate.get_name(), used_crate.get_version());
+        self.get_index_directories("src", used_crate)
+            .into_iter()
+            .map(|directory| directory.join(&directory_name))
+            .find(|path| path.join(".cargo-ok").is_file())
+    }
+
+    // Every registry gets own directories named `<index host>-<hash>`. The
+    // hash depends on the Cargo version, so only the host part is compared.
+    fn get_index_directories(&self, kind: &str, used_crate: &Crate) -> Vec<PathBuf> {
+        let hosts = match used_crate.get_dependency_type() {
+            DependencyTypeEnum::CratesIo => CRATES_IO_DIRECTORIES
+                .iter()
+                .map(|host| host.to_string())
+                .collect(),
+            DependencyTypeEnum::Registry(options) => vec![get_index_host(&options.get_index())],
+            _ => return Vec::new(),
+        };
+
+        let entries = match self
+            .registry_directory
+            .as_ref()
+            .and_then(|directory| read_dir(directory.join(kind)).ok())
+        {
+            Some(entries) => entries,
+            None => return Vec::new(),
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or("");
+                hosts.iter().any(|host| match name.rsplit_once('-') {
+                    Some((prefix, _)) => prefix == host,
+                    None => false,
+                })
+            })
+            .collect()
+    }
+}
+
+// A file in complete checkouts of the cache. Its modification time tells when
+// the checkout was used for the last time.
+const CHECKOUT_MARKER: &str = ".cargo-test-all-ok";
+
+// Temporary copies made by `store` are left alone for this time, since a
+// concurrent run could still be filling them.
+const TEMP_CHECKOUT_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+// Whether the directory holds a checkout restored from the cache.
+pub fn is_cached_checkout(directory: &Path) -> bool {
+    directory.join(CHECKOUT_MARKER).is_file()
+}
+
+// Checkouts downloaded by previous runs, stored under the checkout names of
+// crates, which are unique for every name, version and source.
+#[derive(Debug, Clone)]
+pub struct CheckoutCache {
+    directory: PathBuf,
+}
+
+impl CheckoutCache {
+    pub fn new(directory: &Path) -> Self {
+        CheckoutCache {
+            directory: directory.to_path_buf(),
+        }
+    }
+
+    // Copies the cached checkout of the crate into the destination. Returns
+    // `false` when the crate isn't in the cache.
+    pub fn restore(&self, used_crate: &Crate, destination: &Path) -> Result<bool> {
+        let checkout = self.directory.join(used_crate.get_checkout_name());
+        let marker = checkout.join(CHECKOUT_MARKER);
+        if !marker.is_file() {
+            return Ok(false);
+        }
+
+        File::create(&marker)?.set_modified(SystemTime::now())?;
+        copy_directory(&checkout, destination)?;
+        Ok(true)
+    }
+
+    // Stores a copy of the checkout. The copy is made in a temporary directory
+    // first, so concurrent runs never see partially copied checkouts.
+    pub fn store(&self, used_crate: &Crate, source: &Path) -> Result<()> {
+        let checkout = self.directory.join(used_crate.get_checkout_name());
+        if checkout.exists() {
+            return Ok(());
+        }
+
+        let temp_checkout = self.directory.join(format!(
+            ".tmp-{}-{}",
+            used_crate.get_checkout_name(),
+            process::id()
+        ));
+        copy_directory(source, &temp_checkout)?;
+        File::create(temp_checkout.join(CHECKOUT_MARKER))?;
+        if rename(&temp_checkout, &checkout).is_err() {
+            remove_directory(&temp_checkout)?;
+        }
+        Ok(())
+    }
+
+    // Removes checkouts unused for longer than `max_age` and then the least
+    // recently used ones, until the cache fits into `max_size` bytes. Without
+    // limits everything is removed. Returns the amount and the size of the
+    // removed checkouts.
+    pub fn clean(&self, max_age: Option<Duration>, max_size: Option<u64>) -> Result<(usize, u64)> {
+        let mut checkouts = Vec::new();
+        if self.directory.is_dir() {
+            for entry in read_dir(&self.directory)? {
+                let path = entry?.path();
+                if is_fresh_temp_checkout(&path) {
+                    continue;
+                }
+                let last_used = File::open(path.join(CHECKOUT_MARKER))
+                    .and_then(|marker| marker.metadata())
+                    .and_then(|metadata| metadata.modified())
+                    .ok();
+                checkouts.push((path.clone(), last_used, get_directory_size(&path)));
+            }
+        }
+        checkouts.sort_by_key(|(_, last_used, _)| *last_used);
+
+        let now = SystemTime::now();
+        let mut total_size = checkouts.iter().map(|(_, _, size)| size).sum::<u64>();
+        let mut removed = (0, 0);
+        for (path, last_used, size) in checkouts.into_iter() {
+            // Incomplete checkouts are left by interrupted runs.
+            let is_expired = match (last_used, max_age) {
+                (None, _) => true,
+                (Some(last_used), Some(max_age)) => now
+                    .duration_since(last_used)
+                    .map(|age| age > max_age)
+                    .unwrap_or(false),
+                (Some(_), None) => max_size.is_none(),
+            };
+            let is_oversized = max_size
+                .map(|max_size| total_size > max_size)
+                .unwrap_or(false);
+            if is_expired || is_oversized {
+                remove_directory(&path)?;
+                total_size -= size;
+                removed = (removed.0 + 1, removed.1 + size);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn is_fresh_temp_checkout(directory: &Path) -> bool {
+    let is_temp = directory
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with(".tmp-"))
+        .unwrap_or(false);
+    let age = directory
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .unwrap_or_default();
+    is_temp && age < TEMP_CHECKOUT_LIFETIME
+}
+
+fn remove_directory(directory: &Path) -> Result<()> {
+    remove_dir_all(directory).with_context(|err| ErrorKind::Io {
+        reason: format!("{}", err),
+    })?;
+    Ok(())
+}
+
+fn get_directory_size(directory: &Path) -> u64 {
+    read_dir(directory)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| match entry.file_type() {
+                    Ok(file_type) if file_type.is_dir() => get_directory_size(&entry.path()),
+                    _ => entry.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+                })
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+// Unpacks the `.crate` archive into the destination. Files in archives are
+// stored under the `<name>-<version>/` prefix, which is stripped.
+pub fn extract_archive(archive_path: &Path, destination: &Path) -> Result<()> {
+    let file = File::open(archive_path)?;
+    let mut archive = Archive::new(GzDecoder::new(file));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let relative_path = path
+            .components()
+            .skip(1)
+            .filter_map(|component| match component {
+                Component::Normal(part) => Some(part),
+                _ => None,
+            })
+            .collect::<PathBuf>();
+        if relative_path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let target = destination.join(relative_path);
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+        entry.unpack(&target).with_context(|err| ErrorKind::Io {
+            reason: format!("Can't unpack {}. Reason: {}", archive_path.display(), err),
+        })?;
+    }
+    Ok(())
+}
+
+fn get_index_host(index: &str) -> String {
+    let url = match index.split_once("://") {
+        Some((_, url)) => url,
+        None => index,
+    };
+    let host = url.split('/').next().unwrap_or("");
+    host.split(':').next().unwrap_or("").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::create_dir_all;
+
+    use super::*;
+
+    fn create_cache(name: &str, entries: &[(&str, bool)]) -> CheckoutCache {
+        let directory = temp_dir().join(format!("cargo-test-all-{}-{}", name, process::id()));
+        let _ = remove_dir_all(&directory);
+        for (entry, is_complete) in entries.iter() {
+            create_dir_all(directory.join(entry)).unwrap();
+            if *is_complete {
+                File::create(directory.join(entry).join(CHECKOUT_MARKER)).unwrap();
+            }
+        }
+        CheckoutCache::new(&directory)
+    }
+
+    #[test]
+    fn clean_skips_fresh_temp_checkouts() {
+        let cache = create_cache(
+            "clean-temp",
+            &[
+                ("serde-1.0.0-hash", true),
+                (".tmp-serde-1.0.0-hash-1", false),
+                (".tmp-serde-1.0.0-hash-2", false),
+            ],
+        );
+        // The second checkout was left by a run interrupted long ago.
+        let stale_time = SystemTime::now() - TEMP_CHECKOUT_LIFETIME * 2;
+        File::open(cache.directory.join(".tmp-serde-1.0.0-hash-2"))
+            .unwrap()
+            .set_modified(stale_time)
+            .unwrap();
+        let (removed, _) = cache.clean(None, None).unwrap();
+
+        assert_eq!(removed, 2);
+        assert!(!cache.directory.join("serde-1.0.0-hash").exists());
+        assert!(cache.directory.join(".tmp-serde-1.0.0-hash-1").exists());
+        assert!(!cache.directory.join(".tmp-serde-1.0.0-hash-2").exists());
+        remove_directory(&cache.directory).unwrap();
+    }
+
+    #[test]
+    fn clean_removes_incomplete_checkouts() {
+        let cache = create_cache(
+            "clean-incomplete",
+            &[("serde-1.0.0-hash", true), ("rand-0.8.0-hash", false)],
+        );
+        let (removed, _) = cache.clean(Some(Duration::from_secs(60)), None).unwrap();
+
+        assert_eq!(removed, 1);
+        assert!(cache.directory.join("serde-1.0.0-hash").exists());
+        assert!(!cache.directory.join("rand-0.8.0-hash").exists());
+        remove_directory(&cache.directory).unwrap();
+    }
+}
@@ -1,5 +1,12 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 use structopt::StructOpt;
 
+use crate::command::{DependencyKindEnum, DepthEnum, TestOptions};
+use crate::matrix::{CrateArgs, FeatureMatrixEnum, PassThroughArgs, TestModeEnum};
+use crate::selector::{CrateSelector, SourceKindEnum};
+
 #[derive(StructOpt, Debug)]
 #[structopt(
     name = "cargo-test-all",
@@ -16,7 +23,394 @@ pub struct CliOptions {
     pub threads: usize,
     #[structopt(
         long = "only",
-        help = "List of certain crates for testing, separated by comma."
+        help = "List of certain crates for testing, separated by comma. Supports glob patterns and versions, like `serde@1` or `rand@0.7.*`.",
+        use_delimiter = true
+    )]
+    pub only: Vec<CrateSelector>,
+    #[structopt(
+        long = "exclude",
+        help = "List of crates excluded from testing, separated by comma. Supports the same patterns as --only.",
+        use_delimiter = true
+    )]
+    pub exclude: Vec<CrateSelector>,
+    #[structopt(
+        long = "source",
+        help = "Test only crates from the certain sources (registry, git, path), separated by comma.",
+        use_delimiter = true
+    )]
+    pub source: Vec<SourceKindEnum>,
+    #[structopt(
+        short = "p",
+        long = "package",
+        help = "List of workspace members which dependencies need to test, separated by comma."
+    )]
+    pub package: Option<String>,
+    #[structopt(
+        long = "depth",
+        help = "How deep to go into the dependency graph: a positive number or `all`.",
+        default_value = "1"
+    )]
+    pub depth: DepthEnum,
+    #[structopt(
+        long = "kinds",
+        help = "Kinds of dependencies for testing (normal, build, dev), separated by comma.",
+        default_value = "normal",
+        use_delimiter = true
+    )]
+    pub kinds: Vec<DependencyKindEnum>,
+    #[structopt(
+        long = "target",
+        help = "Target triple for evaluating platform-specific dependencies. Uses the host by default."
+    )]
+    pub target: Option<String>,
+    #[structopt(
+        long = "features",
+        help = "List of the project features to activate, separated by comma."
+    )]
+    pub features: Option<String>,
+    #[structopt(long = "all-features", help = "Activate all features of the project.")]
+    pub all_features: bool,
+    #[structopt(
+        long = "no-default-features",
+        help = "Do not activate the `default` feature of the project."
+    )]
+    pub no_default_features: bool,
+    #[structopt(
+        long = "feature-matrix",
+        help = "Also test each crate with different feature sets: `each` feature separately or their `powerset`."
+    )]
+    pub feature_matrix: Option<FeatureMatrixEnum>,
+    #[structopt(
+        long = "matrix-limit",
+        help = "The maximum amount of feature sets tested for each crate in the feature-matrix mode.",
+        default_value = "32"
+    )]
+    pub matrix_limit: usize,
+    #[structopt(
+        long = "mode",
+        help = "What to do with each crate, separated by comma: `check`, `build`, `test`, `lib` (only library tests), `doctest`, `doc` or `bench` (build benchmarks).",
+        use_delimiter = true,
+        default_value = "test"
+    )]
+    pub modes: Vec<TestModeEnum>,
+    #[structopt(
+        long = "offline",
+        help = "Run without accessing the network: crates are taken from the vendored sources set up via source replacement."
+    )]
+    pub offline: bool,
+    #[structopt(
+        long = "timeout",
+        help = "The maximum time for testing each crate, like `90s`, `15m` or `1h`.",
+        parse(try_from_str = parse_duration)
+    )]
+    pub timeout: Option<Duration>,
+    #[structopt(
+        long = "deadline",
+        help = "The maximum time for the whole run. Crates that aren't tested in time are reported as timed out.",
+        parse(try_from_str = parse_duration)
+    )]
+    pub deadline: Option<Duration>,
+    #[structopt(
+        long = "retries",
+        help = "How many times to rerun failed test cases. Test cases that pass after rerunning are reported as flaky.",
+        default_value = "0"
     )]
-    pub only: Option<String>,
+    pub retries: usize,
+    #[structopt(
+        long = "strict-flaky",
+        help = "Count crates with flaky tests as failed."
+    )]
+    pub strict_flaky: bool,
+    #[structopt(
+        long = "shared-target",
+        help = "Build all crates in one target directory (one per toolchain) to reuse their common dependencies."
+    )]
+    pub shared_target: bool,
+    #[structopt(
+        long = "build-cache",
+        help = "Like --shared-target, but keeps the target directory in the given path between runs, separately for each toolchain.",
+        parse(from_os_str)
+    )]
+    pub build_cache: Option<PathBuf>,
+    #[structopt(
+        long = "toolchains",
+        help = "Test each crate with every given toolchain installed via rustup, like `stable,beta,1.70.0`, separated by comma."
+    )]
+    pub toolchains: Option<String>,
+    #[structopt(
+        long = "msrv",
+        help = "Report crates that declare a higher `rust-version` than the project."
+    )]
+    pub msrv: bool,
+    #[structopt(
+        long = "msrv-test",
+        help = "Like --msrv, but also test each crate with the toolchain of the project's `rust-version`."
+    )]
+    pub msrv_test: bool,
+    #[structopt(
+        long = "integration-lock",
+        help = "Test crates with the versions of their dependencies locked in the project's Cargo.lock."
+    )]
+    pub integration_lock: bool,
+    #[structopt(
+        long = "inject-patches",
+        help = "Apply the `[patch]` and `[replace]` sections of the project to the tested crates, so their tests use the same forks."
+    )]
+    pub inject_patches: bool,
+    #[structopt(
+        long = "cache-dir",
+        help = "Keep downloaded checkouts of crates in the given directory between runs.",
+        parse(from_os_str)
+    )]
+    pub cache_dir: Option<PathBuf>,
+    #[structopt(
+        long = "keep",
+        help = "Leave checkouts of all tested crates in the temporary directory after the run."
+    )]
+    pub keep: bool,
+    #[structopt(
+        long = "keep-failed",
+        help = "Leave checkouts of the failed crates in the temporary directory after the run."
+    )]
+    pub keep_failed: bool,
+    #[structopt(
+        long = "crate-args",
+        help = "Arguments used instead of the ones after `--` for the matched crates, like `serde=--release -- --test-threads=1`. Can be repeated.",
+        number_of_values = 1
+    )]
+    pub crate_args: Vec<CrateArgs>,
+    #[structopt(
+        last = true,
+        help = "Arguments for cargo, optionally followed by `--` and arguments for the test harness."
+    )]
+    pub args: Vec<String>,
+    #[structopt(subcommand)]
+    pub command: Option<CommandEnum>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum CommandEnum {
+    #[structopt(
+        name = "clean",
+        about = "Remove checkouts from the cache directory. Without limits the whole cache is removed."
+    )]
+    Clean {
+        #[structopt(
+            long = "cache-dir",
+            help = "The cache directory used with the --cache-dir option of testing.",
+            parse(from_os_str)
+        )]
+        cache_dir: PathBuf,
+        #[structopt(
+            long = "older-than",
+            help = "Remove checkouts that weren't used for the given time, like `12h` or `30d`.",
+            parse(try_from_str = parse_duration)
+        )]
+        older_than: Option<Duration>,
+        #[structopt(
+            long = "max-size",
+            help = "Remove the least recently used checkouts until the cache fits the given size, like `500M` or `2G`.",
+            parse(try_from_str = parse_size)
+        )]
+        max_size: Option<u64>,
+    },
+}
+
+impl CliOptions {
+    // Cargo runs the binary as `cargo-test-all test-all [args]`, where the
+    // name of the subcommand isn't an argument for the tested crates.
+    pub fn from_cargo_args<I: IntoIterator<Item = String>>(args: I) -> Self {
+        let mut args = args.into_iter().collect::<Vec<String>>();
+        if args.get(1).map(|arg| arg == "test-all").unwrap_or(false) {
+            args.remove(1);
+        }
+        CliOptions::from_iter(args)
+    }
+}
+
+impl From<CliOptions> for TestOptions {
+    fn from(args: CliOptions) -> Self {
+        TestOptions {
+            threads: args.threads,
+            test_only: args.only,
+            exclude: args.exclude,
+            sources: args.source,
+            depth: args.depth,
+            packages: split_list(args.package),
+            kinds: args.kinds,
+            target: args.target,
+            features: split_list(args.features),
+            all_features: args.all_features,
+            no_default_features: args.no_default_features,
+            feature_matrix: args.feature_matrix,
+            matrix_limit: args.matrix_limit,
+            modes: args.modes,
+            offline: args.offline,
+            timeout: args.timeout,
+            deadline: args.deadline,
+            retries: args.retries,
+            strict_flaky: args.strict_flaky,
+            shared_target: args.shared_target,
+            build_cache: args.build_cache,
+            args: PassThroughArgs::new(&args.args),
+            crate_args: args.crate_args,
+            toolchains: split_list(args.toolchains),
+            msrv: args.msrv,
+            msrv_test: args.msrv_test,
+            integration_lock: args.integration_lock,
+            inject_patches: args.inject_patches,
+            cache_directory: args.cache_dir,
+            keep: args.keep,
+            keep_failed: args.keep_failed,
+        }
+    }
+}
+
+fn split_list(value: Option<String>) -> Vec<String> {
+    value
+        .unwrap_or(String::from(""))
+        .split(",")
+        .map(|s| s.to_string().trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+// Parses durations like `500ms`, `30s`, `15m`, `2h` or `7d`. A plain number
+// means seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let index = value
+        .find(|symbol: char| !symbol.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(index);
+    let amount = amount.parse::<u64>().map_err(|_| {
+        format!(
+            "Expected a duration like `30s` or `15m`, but got `{}`.",
+            value
+        )
+    })?;
+
+    let seconds = match unit {
+        "ms" => return Ok(Duration::from_millis(amount)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => {
+            return Err(format!(
+                "Unknown unit `{}` of the duration. Use `ms`, `s`, `m`, `h` or `d`.",
+                unit
+            ))
+        }
+    };
+    amount
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("The duration `{}` is too long.", value))
+}
+
+// Parses sizes like `200K`, `500M` or `2G` in binary units. A plain number
+// means bytes.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let index = value
+        .find(|symbol: char| !symbol.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(index);
+    let amount = amount
+        .parse::<u64>()
+        .map_err(|_| format!("Expected a size like `500M` or `2G`, but got `{}`.", value))?;
+
+    let multiplier: u64 = match unit.to_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => {
+            return Err(format!(
+                "Unknown unit `{}` of the size. Use `K`, `M` or `G`.",
+                unit
+            ))
+        }
+    };
+    amount
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("The size `{}` is too large.", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_options(args: &[&str]) -> CliOptions {
+        CliOptions::from_cargo_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(
+            parse_duration(" 7d "),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid_values() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("10w").is_err());
+        assert!(parse_duration("-5s").is_err());
+    }
+
+    #[test]
+    fn parse_duration_rejects_overflowing_values() {
+        assert!(parse_duration("18446744073709551615d").is_err());
+        assert!(parse_duration("213503982334602d").is_err());
+        assert_eq!(
+            parse_duration("18446744073709551615s"),
+            Ok(Duration::from_secs(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512B"), Ok(512));
+        assert_eq!(parse_size("200K"), Ok(200 << 10));
+        assert_eq!(parse_size("500mb"), Ok(500 << 20));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+    }
+
+    #[test]
+    fn parse_size_rejects_invalid_values() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("5X").is_err());
+        assert!(parse_size("1.5G").is_err());
+    }
+
+    #[test]
+    fn parse_size_rejects_overflowing_values() {
+        assert!(parse_size("17179869184G").is_err());
+        assert!(parse_size("18446744073709551615K").is_err());
+        assert_eq!(parse_size("17179869183G"), Ok(17179869183 << 30));
+    }
+
+    #[test]
+    fn from_cargo_args_skips_subcommand_name() {
+        let options = get_options(&["cargo-test-all", "test-all", "--", "--release"]);
+        assert_eq!(options.args, vec!["--release"]);
+    }
+
+    #[test]
+    fn from_cargo_args_without_subcommand_name() {
+        assert!(get_options(&["cargo-test-all"]).args.is_empty());
+        let options = get_options(&["cargo-test-all", "--", "test-all"]);
+        assert_eq!(options.args, vec!["test-all"]);
+    }
 }
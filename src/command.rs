@@ -1,30 +1,122 @@
-use std::env::{current_dir, set_current_dir};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::env::{current_dir, set_var, temp_dir};
+use std::fmt;
 use std::fs::create_dir_all;
-use std::path::Path;
+use std::iter::once;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::str::FromStr;
 use std::sync::mpsc::channel;
+use std::time::Duration;
 
+use cargo::core::dependency::DepKind;
 use cargo::core::{Dependency, GitReference};
+use cargo::sources::CRATES_IO_INDEX;
+use cargo::util::{short_hash, ToSemver};
+use cargo::Config;
 use rm_rf::remove as remove_dir_all;
 use workerpool::thunk::{Thunk, ThunkWorker};
 use workerpool::Pool;
 
-use crate::error::{ErrorKind, Result};
+use crate::cache::CheckoutCache;
+use crate::error::{Error, ErrorKind, Result};
+use crate::lockfile::{find_lockfile, get_locked_commit, matches_source, LockedPackage, Lockfile};
+use crate::matrix::{
+    get_test_configurations, with_modes, with_toolchains, CrateArgs, FeatureMatrixEnum,
+    PassThroughArgs, TestModeEnum,
+};
+use crate::metadata::{DependencyGraph, MetadataPackage};
+use crate::patch::Patches;
+use crate::platform::{
+    check_toolchains, combine_platforms, compare_rust_versions, get_toolchain_id, TargetPlatform,
+};
+use crate::process::TimeLimits;
+use crate::report::{
+    print_build_summary, print_checkout_summary, print_feature_matrix, print_flaky_crates,
+    print_msrv_violations, print_toolchain_grid, print_warnings, CrateReport,
+};
+use crate::selector::{CrateSelector, SourceKindEnum};
 use crate::util::{get_project_location, load_cargo_toml};
-use crate::worker::run_crate_tests;
+use crate::vendor::VendoredSources;
+use crate::worker::{run_crate_tests, CheckoutOptions};
 use failure::ResultExt;
 
 #[derive(Debug, Clone)]
 pub enum DependencyTypeEnum {
-    CratesIo(String),
+    CratesIo,
+    Registry(RegistryOptions),
     Git(SourceOptions),
     Local,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DependencyKindEnum {
+    Normal,
+    Build,
+    Dev,
+}
+
+impl From<DepKind> for DependencyKindEnum {
+    fn from(kind: DepKind) -> Self {
+        match kind {
+            DepKind::Normal => DependencyKindEnum::Normal,
+            DepKind::Build => DependencyKindEnum::Build,
+            DepKind::Development => DependencyKindEnum::Dev,
+        }
+    }
+}
+
+impl FromStr for DependencyKindEnum {
+    type Err = String;
+
+    fn from_str(value: &str) -> result::Result<Self, Self::Err> {
+        match value.trim() {
+            "normal" => Ok(DependencyKindEnum::Normal),
+            "build" => Ok(DependencyKindEnum::Build),
+            "dev" => Ok(DependencyKindEnum::Dev),
+            _ => Err(format!(
+                "Expected `normal`, `build` or `dev`, but got `{}`.",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DependencyKindEnum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DependencyKindEnum::Normal => write!(f, "normal"),
+            DependencyKindEnum::Build => write!(f, "build"),
+            DependencyKindEnum::Dev => write!(f, "dev"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RegistryOptions {
+    name: Option<String>,
+    index: String,
+}
+
+impl RegistryOptions {
+    // The name of the registry from `[registries]` table of Cargo config. It
+    // is known only for dependencies declared with the `registry` key.
+    pub fn get_name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    pub fn get_index(&self) -> String {
+        self.index.clone()
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct SourceOptions {
     branch: Option<String>,
     tag: Option<String>,
     commit: Option<String>,
+    locked_commit: Option<String>,
 }
 
 impl SourceOptions {
@@ -39,13 +131,29 @@ impl SourceOptions {
     pub fn get_commit(&self) -> Option<String> {
         self.commit.clone()
     }
+
+    // The exact commit from Cargo.lock, which the build actually uses.
+    pub fn get_locked_commit(&self) -> Option<String> {
+        self.locked_commit.clone()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Crate {
     name: String,
+    renames: Vec<String>,
+    version: String,
+    checksum: Option<String>,
     path: String,
     dependency_type: DependencyTypeEnum,
+    kinds: Vec<DependencyKindEnum>,
+    features: Vec<String>,
+    available_features: Vec<String>,
+    platforms: Vec<Option<String>>,
+    required_by: Vec<String>,
+    vendored_path: Option<String>,
+    rust_version: Option<String>,
+    is_patched: bool,
 }
 
 impl Crate {
@@ -53,17 +161,145 @@ impl Crate {
         self.name.clone()
     }
 
+    // Names under which the crate is declared in `[dependencies]` tables via
+    // the `package` key, when they differ from the package name.
+    pub fn get_renames(&self) -> Vec<String> {
+        self.renames.clone()
+    }
+
+    pub fn get_version(&self) -> String {
+        self.version.clone()
+    }
+
+    // A directory name that is unique for every package, version and source,
+    // so different versions of a crate are never checked out into one place.
+    // Git sources include the locked commit, because it isn't a part of the
+    // version.
+    pub fn get_checkout_name(&self) -> String {
+        let source = match &self.dependency_type {
+            DependencyTypeEnum::Git(options) => format!(
+                "{}#{}",
+                self.path,
+                options.get_locked_commit().unwrap_or_default()
+            ),
+            _ => self.path.clone(),
+        };
+        format!("{}-{}-{}", self.name, self.version, short_hash(&source))
+    }
+
+    pub fn get_checksum(&self) -> Option<String> {
+        self.checksum.clone()
+    }
+
     pub fn get_path(&self) -> String {
         self.path.clone()
     }
 
+    // A directory with the copy of the crate made by `cargo vendor`, which is
+    // used instead of downloading the crate in the offline mode.
+    pub fn get_vendored_path(&self) -> Option<String> {
+        self.vendored_path.clone()
+    }
+
+    // The minimal Rust version declared by the crate as `rust-version`.
+    pub fn get_rust_version(&self) -> Option<String> {
+        self.rust_version.clone()
+    }
+
     pub fn get_dependency_type(&self) -> DependencyTypeEnum {
         self.dependency_type.clone()
     }
-}
 
-impl From<Dependency> for Crate {
-    fn from(dependency: Dependency) -> Self {
+    pub fn get_kinds(&self) -> Vec<DependencyKindEnum> {
+        self.kinds.clone()
+    }
+
+    pub fn add_kind(&mut self, kind: DependencyKindEnum) {
+        if !self.kinds.contains(&kind) {
+            self.kinds.push(kind);
+        }
+    }
+
+    // The feature set unified by the resolver for the whole project, that
+    // already includes `default` when the default features are enabled.
+    pub fn get_features(&self) -> Vec<String> {
+        self.features.clone()
+    }
+
+    // All features declared by the crate, including the implicit ones for
+    // its optional dependencies.
+    pub fn get_available_features(&self) -> Vec<String> {
+        self.available_features.clone()
+    }
+
+    pub fn get_cargo_features_args(&self) -> Vec<String> {
+        let mut args = vec![String::from("--no-default-features")];
+        if !self.features.is_empty() {
+            args.push(String::from("--features"));
+            args.push(self.features.join(","));
+        }
+        args
+    }
+
+    // Every entry is a `cfg(...)` expression or a target triple from the
+    // `[target]` table, while `None` means the crate is used everywhere.
+    pub fn get_platforms(&self) -> Vec<Option<String>> {
+        self.platforms.clone()
+    }
+
+    pub fn add_platform(&mut self, platform: Option<String>) {
+        if !self.platforms.contains(&platform) {
+            self.platforms.push(platform);
+        }
+    }
+
+    pub fn is_used_on(&self, target_platform: &TargetPlatform) -> bool {
+        self.platforms.iter().any(|platform| match platform {
+            Some(platform) => target_platform.matches(platform),
+            None => true,
+        })
+    }
+
+    pub fn get_required_by(&self) -> Vec<String> {
+        self.required_by.clone()
+    }
+
+    pub fn add_required_by(&mut self, member: &str) {
+        if !self.required_by.iter().any(|name| name == member) {
+            self.required_by.push(member.to_string());
+        }
+    }
+
+    pub fn merge(&mut self, other: &Crate) {
+        for rename in other.renames.iter() {
+            if !self.renames.contains(rename) {
+                self.renames.push(rename.to_owned());
+            }
+        }
+        other.kinds.iter().for_each(|kind| self.add_kind(*kind));
+        for feature in other.features.iter() {
+            if !self.features.contains(feature) {
+                self.features.push(feature.to_owned());
+            }
+        }
+        other
+            .platforms
+            .iter()
+            .for_each(|platform| self.add_platform(platform.clone()));
+        other
+            .required_by
+            .iter()
+            .for_each(|member| self.add_required_by(member));
+    }
+
+    // The version and the source (with the locked commit of Git ones) are
+    // the ones the dependency is resolved to.
+    pub fn new(
+        dependency: &Dependency,
+        version: &str,
+        source: Option<&str>,
+        checksum: Option<String>,
+    ) -> Self {
         let name = dependency.package_name().to_string();
         let source_id = dependency.source_id();
         let mut path = source_id.url().to_string();
@@ -72,10 +308,12 @@ impl From<Dependency> for Crate {
         let is_registry = source_id.is_registry();
         let is_local = source_id.is_path();
         let dependency_type = match (is_registry, is_git, is_local) {
-            (true, _, _) => {
-                let version = extract_version_from_dependency(&dependency);
-                DependencyTypeEnum::CratesIo(version)
-            }
+            (true, _, _) if source_id.is_default_registry() => DependencyTypeEnum::CratesIo,
+            (true, _, _) => DependencyTypeEnum::Registry(RegistryOptions {
+                name: Some(source_id.display_registry_name())
+                    .filter(|name| is_configured_registry(name, &path)),
+                index: path.clone(),
+            }),
             (_, true, _) => {
                 let mut branch = None;
                 let mut tag = None;
@@ -90,6 +328,7 @@ impl From<Dependency> for Crate {
                     branch,
                     tag,
                     commit,
+                    locked_commit: source.and_then(get_locked_commit),
                 })
             }
             (_, _, true) => {
@@ -101,60 +340,390 @@ impl From<Dependency> for Crate {
 
         Crate {
             name,
+            renames: dependency
+                .explicit_name_in_toml()
+                .map(|rename| vec![rename.to_string()])
+                .unwrap_or_default(),
+            version: version.to_string(),
+            checksum,
+            path,
+            dependency_type,
+            kinds: vec![DependencyKindEnum::from(dependency.kind())],
+            features: Vec::new(),
+            available_features: Vec::new(),
+            platforms: vec![dependency.platform().map(|platform| platform.to_string())],
+            required_by: Vec::new(),
+            vendored_path: None,
+            rust_version: None,
+            is_patched: false,
+        }
+    }
+
+    pub fn from_package(
+        package: &MetadataPackage,
+        kind: DependencyKindEnum,
+        platform: Option<String>,
+        features: Vec<String>,
+        locked_package: Option<&LockedPackage>,
+    ) -> Self {
+        let source = package.get_source().unwrap_or_default();
+        let (path, dependency_type) = match source.split_once('+') {
+            Some(("git", url)) => {
+                let (url, options) = parse_git_source(url);
+                (url, DependencyTypeEnum::Git(options))
+            }
+            Some((_, url)) if url == CRATES_IO_INDEX => {
+                (url.to_string(), DependencyTypeEnum::CratesIo)
+            }
+            Some((_, url)) => {
+                let options = RegistryOptions {
+                    name: None,
+                    index: url.to_string(),
+                };
+                (url.to_string(), DependencyTypeEnum::Registry(options))
+            }
+            None => (package.get_directory(), DependencyTypeEnum::Local),
+        };
+
+        Crate {
+            name: package.get_name(),
+            renames: Vec::new(),
+            version: package.get_version(),
+            checksum: locked_package.and_then(|package| package.get_checksum()),
             path,
             dependency_type,
+            kinds: vec![kind],
+            features,
+            available_features: package.get_features(),
+            platforms: vec![platform],
+            required_by: Vec::new(),
+            vendored_path: None,
+            rust_version: package.get_rust_version(),
+            is_patched: false,
         }
     }
+
+    // Identifies the crate by name, version and source, so that the same
+    // package reached through different paths in the graph is tested once.
+    pub fn get_id(&self) -> String {
+        format!("{}@{}@{}", self.name, self.version, self.path)
+    }
 }
 
-fn extract_version_from_dependency(dependency: &Dependency) -> String {
-    let version = dependency
-        .version_req()
-        .to_string()
-        .replace("^", "")
-        .replace("~", "")
-        .replace("<", "")
-        .replace(">", "")
-        .replace("=", "");
+impl fmt::Display for Crate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds = self
+            .kinds
+            .iter()
+            .map(|kind| kind.to_string())
+            .collect::<Vec<String>>();
+        write!(f, "{} v{} [{}]", self.name, self.version, kinds.join(", "))?;
+        if !self.renames.is_empty() {
+            write!(f, " (renamed: {})", self.renames.join(", "))?;
+        }
+        if let DependencyTypeEnum::Registry(options) = &self.dependency_type {
+            let registry = options.get_name().unwrap_or_else(|| options.get_index());
+            write!(f, " (registry: {})", registry)?;
+        }
+        if self.is_patched {
+            write!(f, " (patched)")?;
+        }
+        if self.vendored_path.is_some() {
+            write!(f, " (vendored)")?;
+        }
+        if !self.required_by.is_empty() {
+            write!(f, " (required by: {})", self.required_by.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn is_configured_registry(name: &str, index: &str) -> bool {
+    match Config::default() {
+        Ok(config) => config
+            .get_registry_index(name)
+            .map(|url| url.as_str().trim_end_matches('/') == index.trim_end_matches('/'))
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+fn parse_git_source(source: &str) -> (String, SourceOptions) {
+    let (source, locked_commit) = match source.split_once('#') {
+        Some((source, commit)) => (source, Some(commit.to_string())),
+        None => (source, None),
+    };
+    let (url, query) = source.split_once('?').unwrap_or((source, ""));
 
-    match version.matches(".").count() {
-        0 => format!("{}.0.0", version),
-        1 => format!("{}.0", version),
-        _ => version,
+    let mut options = SourceOptions {
+        locked_commit,
+        ..SourceOptions::default()
+    };
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        match key {
+            "branch" => options.branch = Some(value.to_string()),
+            "tag" => options.tag = Some(value.to_string()),
+            "rev" => options.commit = Some(value.to_string()),
+            _ => (),
+        }
+    }
+
+    (url.to_string(), options)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthEnum {
+    Limited(usize),
+    All,
+}
+
+impl DepthEnum {
+    pub fn includes(&self, level: usize) -> bool {
+        match self {
+            DepthEnum::Limited(depth) => level <= *depth,
+            DepthEnum::All => true,
+        }
+    }
+}
+
+impl FromStr for DepthEnum {
+    type Err = String;
+
+    fn from_str(value: &str) -> result::Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(DepthEnum::All),
+            _ => match value.parse::<usize>() {
+                Ok(depth) if depth > 0 => Ok(DepthEnum::Limited(depth)),
+                _ => Err(format!(
+                    "Expected a positive number or `all`, but got `{}`.",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReasonEnum {
+    Platform,
+    NotActivated,
+    NotResolved,
+}
+
+impl fmt::Display for SkipReasonEnum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkipReasonEnum::Platform => write!(f, "platform"),
+            SkipReasonEnum::NotActivated => write!(f, "not activated"),
+            SkipReasonEnum::NotResolved => write!(f, "not resolved"),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct CrateList {
     all: Box<Vec<Crate>>,
+    skipped: Vec<(Crate, SkipReasonEnum)>,
     failed: Box<Vec<ErrorKind>>,
+    reports: Vec<CrateReport>,
+    rust_version: Option<String>,
+    patches: Patches,
 }
 
 impl CrateList {
-    pub fn load(path: &Path) -> Result<Self> {
-        let cargo_toml_path = path.join("Cargo.toml");
-        let cargo_toml = load_cargo_toml(&cargo_toml_path)?;
+    pub fn load(path: &Path, options: &TestOptions) -> Result<Self> {
+        let lockfile = Lockfile::load(path)?;
+        let graph = DependencyGraph::load(path, &options.get_feature_args())?;
+        let patches = Patches::load(Path::new(&graph.get_workspace_root()))?;
+        let members = select_workspace_members(path, &graph, &options.packages)?;
+        let member_directories = graph
+            .get_workspace_members()
+            .iter()
+            .map(|member| member.get_directory())
+            .collect::<HashSet<String>>();
 
-        let used_crates = cargo_toml
-            .dependencies()
-            .into_iter()
-            .map(|dependency| Crate::from(dependency.to_owned()))
-            .collect::<Vec<Crate>>();
+        let mut used_crates: Vec<Crate> = Vec::new();
+        let mut skipped_crates: Vec<(Crate, SkipReasonEnum)> = Vec::new();
+        let mut rust_version: Option<String> = None;
+        for member in members {
+            let cargo_toml_path = Path::new(&member.get_directory()).join("Cargo.toml");
+            let cargo_toml = load_cargo_toml(&cargo_toml_path)?;
+            // The lowest version among the members is the one all of them
+            // have to be built with.
+            if let Some(member_version) = cargo_toml.rust_version() {
+                let is_lower = rust_version.as_deref().is_none_or(|version| {
+                    compare_rust_versions(member_version, version) == Ordering::Less
+                });
+                if is_lower {
+                    rust_version = Some(member_version.to_string());
+                }
+            }
+            let mut member_crates = Vec::new();
+            for dependency in cargo_toml.dependencies().iter() {
+                let (dependency, resolved_package) =
+                    resolve_dependency(&graph, &member.get_id(), &patches, dependency);
+                let dependency = &dependency;
+                let mut used_crate = match resolved_package {
+                    Some(package) => {
+                        let source = package.get_source();
+                        let checksum = lockfile
+                            .find_package(
+                                &package.get_name(),
+                                &package.get_version(),
+                                source.as_deref(),
+                            )
+                            .and_then(|locked_package| locked_package.get_checksum());
+                        Crate::new(
+                            dependency,
+                            &package.get_version(),
+                            source.as_deref(),
+                            checksum,
+                        )
+                    }
+                    // Dependencies left out by the resolver are only reported,
+                    // so the version they would be locked with is good enough.
+                    None => {
+                        let locked_package =
+                            lockfile.find_dependency(dependency).ok_or_else(|| {
+                                Error::from(ErrorKind::MissingLockedPackage {
+                                    crate_name: dependency.package_name().to_string(),
+                                })
+                            })?;
+                        Crate::new(
+                            dependency,
+                            &locked_package.get_version(),
+                            locked_package.get_source().as_deref(),
+                            locked_package.get_checksum(),
+                        )
+                    }
+                };
+                if member_directories.contains(&used_crate.path) {
+                    continue;
+                }
+
+                // The resolver leaves out optional dependencies that none of
+                // the selected features activates. Features of other missing
+                // dependencies are unknown, so they aren't tested either.
+                match resolved_package {
+                    Some(package) => {
+                        used_crate.features = graph.get_features(&package.get_id());
+                        used_crate.available_features = package.get_features();
+                        used_crate.rust_version = package.get_rust_version();
+                        member_crates.push(used_crate);
+                    }
+                    None if dependency.is_optional() => {
+                        used_crate.add_required_by(&member.get_name());
+                        skipped_crates.push((used_crate, SkipReasonEnum::NotActivated));
+                    }
+                    None => {
+                        used_crate.add_required_by(&member.get_name());
+                        skipped_crates.push((used_crate, SkipReasonEnum::NotResolved));
+                    }
+                }
+            }
+
+            if options.depth != DepthEnum::Limited(1) {
+                let transitive_crates =
+                    collect_transitive_crates(&graph, &lockfile, options.depth, &member.get_id());
+                member_crates.extend(transitive_crates);
+            }
+
+            for mut used_crate in member_crates {
+                used_crate.add_required_by(&member.get_name());
+                used_crate.is_patched = patches.is_patched(&used_crate);
+                match used_crates
+                    .iter_mut()
+                    .find(|known_crate| known_crate.get_id() == used_crate.get_id())
+                {
+                    Some(known_crate) => known_crate.merge(&used_crate),
+                    None => used_crates.push(used_crate),
+                }
+            }
+        }
+
+        let mut skipped = Vec::new();
+        for (skipped_crate, reason) in skipped_crates {
+            let is_used = used_crates
+                .iter()
+                .chain(skipped.iter().map(|(obj, _)| obj))
+                .any(|obj: &Crate| obj.get_id() == skipped_crate.get_id());
+            if !is_used {
+                skipped.push((skipped_crate, reason));
+            }
+        }
 
         Ok(CrateList {
             all: Box::new(used_crates),
+            skipped,
             failed: Box::new(Vec::new()),
+            reports: Vec::new(),
+            rust_version,
+            patches,
         })
     }
 
-    pub fn with_filter_crates(mut self, test_only: &Vec<String>) -> Self {
+    pub fn with_filter_crates(mut self, test_only: &[CrateSelector]) -> Result<Self> {
+        let known_crates = self
+            .all
+            .iter()
+            .chain(self.skipped.iter().map(|(obj, _)| obj))
+            .collect::<Vec<&Crate>>();
+        for selector in test_only.iter().filter(|selector| !selector.is_pattern()) {
+            if !known_crates.iter().any(|obj| selector.matches(obj)) {
+                let names = known_crates
+                    .iter()
+                    .flat_map(|obj| {
+                        let mut names = obj.get_renames();
+                        names.push(obj.get_name());
+                        names
+                    })
+                    .collect::<Vec<String>>();
+                let close_matches = selector.get_close_matches(&names);
+                let hint = match close_matches.is_empty() {
+                    true => String::new(),
+                    false => format!(" Did you mean: {}?", close_matches.join(", ")),
+                };
+                return Err(Error::from(ErrorKind::UnknownCrate {
+                    selector: selector.to_string(),
+                    hint,
+                }));
+            }
+        }
+
         match test_only.is_empty() {
             true => (),
             false => {
                 self.all = Box::new(
                     self.all
                         .into_iter()
-                        .filter(|obj| test_only.contains(&obj.name))
+                        .filter(|obj| test_only.iter().any(|selector| selector.matches(obj)))
+                        .collect(),
+                );
+            }
+        };
+
+        Ok(self)
+    }
+
+    pub fn with_exclude_crates(mut self, exclude: &[CrateSelector]) -> Self {
+        self.all = Box::new(
+            self.all
+                .into_iter()
+                .filter(|obj| !exclude.iter().any(|selector| selector.matches(obj)))
+                .collect(),
+        );
+
+        self
+    }
+
+    pub fn with_filter_sources(mut self, sources: &[SourceKindEnum]) -> Self {
+        match sources.is_empty() {
+            true => (),
+            false => {
+                self.all = Box::new(
+                    self.all
+                        .into_iter()
+                        .filter(|obj| sources.iter().any(|source| source.matches(obj)))
                         .collect(),
                 );
             }
@@ -163,10 +732,65 @@ impl CrateList {
         self
     }
 
+    pub fn with_filter_kinds(mut self, kinds: &[DependencyKindEnum]) -> Self {
+        self.all = Box::new(
+            self.all
+                .into_iter()
+                .filter(|obj| obj.kinds.iter().any(|kind| kinds.contains(kind)))
+                .collect(),
+        );
+
+        self
+    }
+
+    pub fn with_filter_platform(mut self, target_platform: &TargetPlatform) -> Self {
+        let (used_crates, skipped_crates): (Vec<Crate>, Vec<Crate>) = self
+            .all
+            .into_iter()
+            .partition(|obj| obj.is_used_on(target_platform));
+
+        self.all = Box::new(used_crates);
+        self.skipped.extend(
+            skipped_crates
+                .into_iter()
+                .map(|obj| (obj, SkipReasonEnum::Platform)),
+        );
+        self
+    }
+
+    // Points crates from registries and Git to their vendored copies. Crates
+    // that aren't vendored can't be tested offline and reported as failed.
+    pub fn with_vendored_sources(mut self, vendored_sources: &VendoredSources) -> Self {
+        let mut used_crates = Vec::new();
+        for mut used_crate in self.all.clone().into_iter() {
+            if let DependencyTypeEnum::Local = used_crate.get_dependency_type() {
+                used_crates.push(used_crate);
+                continue;
+            }
+
+            match vendored_sources.find_crate(&used_crate) {
+                Some(directory) => {
+                    used_crate.vendored_path = directory.to_str().map(|path| path.to_string());
+                    used_crates.push(used_crate);
+                }
+                None => self.append_error(&ErrorKind::NotVendored {
+                    crate_name: used_crate.get_name(),
+                }),
+            }
+        }
+
+        self.all = Box::new(used_crates);
+        self
+    }
+
     pub fn get_tested_crates_list(&self) -> &Box<Vec<Crate>> {
         &self.all
     }
 
+    pub fn get_skipped_crates(&self) -> &Vec<(Crate, SkipReasonEnum)> {
+        &self.skipped
+    }
+
     pub fn get_failed_crates(&self) -> &Box<Vec<ErrorKind>> {
         &self.failed
     }
@@ -175,44 +799,442 @@ impl CrateList {
         self.failed.push(error.clone());
     }
 
+    pub fn get_patches(&self) -> &Patches {
+        &self.patches
+    }
+
+    // The minimal supported Rust version of the selected workspace members.
+    pub fn get_rust_version(&self) -> Option<String> {
+        self.rust_version.clone()
+    }
+
+    pub fn get_reports(&self) -> &Vec<CrateReport> {
+        &self.reports
+    }
+
+    // Flaky crates are counted as failed only when `strict_flaky` is set.
+    pub fn append_report(&mut self, report: CrateReport, strict_flaky: bool) {
+        if report.is_timed_out() {
+            self.append_error(&ErrorKind::TestsTimeout {
+                crate_name: report.get_crate().get_name(),
+                output: report.get_failure_output(),
+            });
+        } else if report.is_failed() {
+            self.append_error(&ErrorKind::TestsFailure {
+                crate_name: report.get_crate().get_name(),
+                output: report.get_failure_output(),
+            });
+        } else if strict_flaky && !report.get_flaky_tests().is_empty() {
+            self.append_error(&ErrorKind::FlakyTests {
+                crate_name: report.get_crate().get_name(),
+                tests: report.get_flaky_tests().join(", "),
+            });
+        }
+        self.reports.push(report);
+    }
+
     pub fn has_failed_tests(&self) -> bool {
         !self.failed.is_empty()
     }
 }
 
+// Running at the workspace root tests dependencies of every member (or only
+// of the requested ones), otherwise only of the package in the current
+// directory, as it was before.
+fn select_workspace_members<'a>(
+    path: &Path,
+    graph: &'a DependencyGraph,
+    packages: &[String],
+) -> Result<Vec<&'a MetadataPackage>> {
+    let members = graph.get_workspace_members();
+    if !packages.is_empty() {
+        for package in packages.iter() {
+            if !members.iter().any(|member| &member.get_name() == package) {
+                return Err(Error::from(ErrorKind::UnknownPackage {
+                    package: package.to_owned(),
+                }));
+            }
+        }
+
+        return Ok(members
+            .into_iter()
+            .filter(|member| packages.contains(&member.get_name()))
+            .collect());
+    }
+
+    match path == Path::new(&graph.get_workspace_root()) {
+        true => Ok(members),
+        false => Ok(members
+            .into_iter()
+            .filter(|member| Path::new(&member.get_directory()) == path)
+            .collect()),
+    }
+}
+
+// Patched dependencies are resolved to the source of the patch, so that source
+// is tested instead of the original one, unless Cargo left the patch unused.
+fn resolve_dependency<'a>(
+    graph: &'a DependencyGraph,
+    member_id: &str,
+    patches: &Patches,
+    dependency: &Dependency,
+) -> (Dependency, Option<&'a MetadataPackage>) {
+    let resolved = patches
+        .apply(dependency)
+        .into_iter()
+        .chain(once(dependency.clone()))
+        .find_map(|candidate| {
+            find_resolved_package(graph, member_id, &candidate).map(|package| (candidate, package))
+        });
+    match resolved {
+        Some((candidate, package)) => (candidate, Some(package)),
+        None => (dependency.clone(), None),
+    }
+}
+
+// Follows the edge of the graph from the member to the package the resolver
+// picked for the dependency. Edges are named by the key in Cargo.toml (with
+// dashes replaced by underscores), unless the library target of the package
+// has a name of its own, so the version requirement is the last resort.
+fn find_resolved_package<'a>(
+    graph: &'a DependencyGraph,
+    member_id: &str,
+    dependency: &Dependency,
+) -> Option<&'a MetadataPackage> {
+    let candidates = graph
+        .get_dependency_names(member_id)
+        .into_iter()
+        .filter(|(package, _)| package.get_name() == dependency.package_name().as_str())
+        .filter(|(package, _)| {
+            matches_source(package.get_source().as_deref(), dependency.source_id())
+        })
+        .collect::<Vec<(&MetadataPackage, String)>>();
+    let key = dependency.name_in_toml().replace('-', "_");
+
+    candidates
+        .iter()
+        .find(|(_, name)| *name == key)
+        .or_else(|| {
+            candidates.iter().find(|(package, _)| {
+                package
+                    .get_version()
+                    .to_semver()
+                    .map(|version| dependency.version_req().matches(&version))
+                    .unwrap_or(false)
+            })
+        })
+        .map(|(package, _)| *package)
+}
+
+// Walks the resolved dependency graph level by level, starting from the
+// dependencies of the dependencies declared in Cargo.toml. The direct ones
+// are taken from the manifest instead, because it knows more about them.
+fn collect_transitive_crates(
+    graph: &DependencyGraph,
+    lockfile: &Lockfile,
+    depth: DepthEnum,
+    root: &str,
+) -> Vec<Crate> {
+    let mut crates: Vec<Crate> = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current_level: Vec<(String, DependencyKindEnum, Option<String>)> =
+        vec![(root.to_string(), DependencyKindEnum::Normal, None)];
+    let mut level = 1;
+    while !current_level.is_empty() && depth.includes(level) {
+        let mut next_level = Vec::new();
+        for (id, parent_kind, parent_platform) in current_level.iter() {
+            for (package, edge_kinds) in graph.get_dependencies(id) {
+                for (edge_kind, edge_platform) in edge_kinds {
+                    // Everything pulled in by a build or a dev dependency stays
+                    // out of the shipped artifact, so it keeps the parent's kind.
+                    let kind = match parent_kind {
+                        DependencyKindEnum::Normal => edge_kind,
+                        _ => *parent_kind,
+                    };
+                    let platform = match (parent_platform, edge_platform) {
+                        (Some(parent), Some(edge)) => Some(combine_platforms(parent, &edge)),
+                        (parent, edge) => edge.or_else(|| parent.clone()),
+                    };
+                    let state = (package.get_id(), kind, platform.clone());
+                    if package.get_id() == root || !visited.insert(state) {
+                        continue;
+                    }
+
+                    if level > 1 && !graph.is_workspace_member(&package.get_id()) {
+                        let locked_package = lockfile.find_package(
+                            &package.get_name(),
+                            &package.get_version(),
+                            package.get_source().as_deref(),
+                        );
+                        let used_crate = Crate::from_package(
+                            package,
+                            kind,
+                            platform.clone(),
+                            graph.get_features(&package.get_id()),
+                            locked_package,
+                        );
+                        match crates
+                            .iter_mut()
+                            .find(|known_crate| known_crate.get_id() == used_crate.get_id())
+                        {
+                            Some(known_crate) => known_crate.merge(&used_crate),
+                            None => crates.push(used_crate),
+                        }
+                    }
+                    next_level.push((package.get_id(), kind, platform));
+                }
+            }
+        }
+
+        current_level = next_level;
+        level += 1;
+    }
+
+    crates
+}
+
 #[derive(Debug, Clone)]
 pub struct TestOptions {
     pub threads: usize,
-    pub test_only: Vec<String>,
+    pub test_only: Vec<CrateSelector>,
+    pub exclude: Vec<CrateSelector>,
+    pub sources: Vec<SourceKindEnum>,
+    pub depth: DepthEnum,
+    pub packages: Vec<String>,
+    pub kinds: Vec<DependencyKindEnum>,
+    pub target: Option<String>,
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+    pub feature_matrix: Option<FeatureMatrixEnum>,
+    pub matrix_limit: usize,
+    pub modes: Vec<TestModeEnum>,
+    pub offline: bool,
+    pub timeout: Option<Duration>,
+    pub deadline: Option<Duration>,
+    pub retries: usize,
+    pub strict_flaky: bool,
+    pub shared_target: bool,
+    pub build_cache: Option<PathBuf>,
+    pub args: PassThroughArgs,
+    pub crate_args: Vec<CrateArgs>,
+    pub toolchains: Vec<String>,
+    pub msrv: bool,
+    pub msrv_test: bool,
+    pub integration_lock: bool,
+    pub inject_patches: bool,
+    pub cache_directory: Option<PathBuf>,
+    pub keep: bool,
+    pub keep_failed: bool,
+}
+
+impl TestOptions {
+    // Features of the tested project, that are passed to `cargo metadata` to
+    // resolve the same feature set for dependencies as the build does.
+    pub fn get_feature_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.features.is_empty() {
+            args.push(String::from("--features"));
+            args.push(self.features.join(","));
+        }
+        if self.all_features {
+            args.push(String::from("--all-features"));
+        }
+        if self.no_default_features {
+            args.push(String::from("--no-default-features"));
+        }
+        args
+    }
+
+    // Pass-through arguments for the crate: the last of the matched
+    // per-crate ones, otherwise the common ones.
+    pub fn get_pass_through_args(&self, used_crate: &Crate) -> PassThroughArgs {
+        self.crate_args
+            .iter()
+            .rev()
+            .find(|crate_args| crate_args.matches(used_crate))
+            .map(|crate_args| crate_args.get_args())
+            .unwrap_or_else(|| self.args.clone())
+    }
 }
 
 pub fn test_crates(options: &TestOptions) -> Result<()> {
+    // Cargo commands started by this process and by the runners inherit the
+    // variable, so nothing is fetched from the network in the offline mode.
+    if options.offline {
+        set_var("CARGO_NET_OFFLINE", "true");
+    }
+
     let project_location = get_project_location()?;
-    let mut crate_list =
-        CrateList::load(project_location.as_path())?.with_filter_crates(&options.test_only);
+    let target_platform = TargetPlatform::load(options.target.as_deref())?;
+    let mut crate_list = CrateList::load(project_location.as_path(), options)?
+        .with_filter_crates(&options.test_only)?
+        .with_exclude_crates(&options.exclude)
+        .with_filter_sources(&options.sources)
+        .with_filter_kinds(&options.kinds)
+        .with_filter_platform(&target_platform);
+    if options.offline {
+        let vendored_sources = VendoredSources::load(project_location.as_path())?;
+        crate_list = crate_list.with_vendored_sources(&vendored_sources);
+    }
+
+    // Testing of the MSRV adds the toolchain of the project's `rust-version`
+    // to the other ones, or to the default one (`None`) when there are none.
+    let rust_version = match options.msrv || options.msrv_test {
+        true => Some(
+            crate_list
+                .get_rust_version()
+                .ok_or_else(|| Error::from(ErrorKind::MissingRustVersion))?,
+        ),
+        false => None,
+    };
+    let mut toolchains = options
+        .toolchains
+        .iter()
+        .cloned()
+        .map(Some)
+        .collect::<Vec<Option<String>>>();
+    if let (Some(rust_version), true) = (&rust_version, options.msrv_test) {
+        if toolchains.is_empty() {
+            toolchains.push(None);
+        }
+        if !toolchains.contains(&Some(rust_version.to_owned())) {
+            toolchains.push(Some(rust_version.to_owned()));
+        }
+    }
+    check_toolchains(
+        &toolchains
+            .iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<String>>(),
+    )?;
 
     let parent_directory = current_dir()?;
-    let temp_directory = parent_directory.join("target/testing/deps");
+    let testing_directory = get_testing_directory(&parent_directory);
+    // Checkouts kept by the previous run are replaced by fresh ones.
+    let temp_directory = testing_directory.join("deps");
+    if temp_directory.exists() {
+        remove_dir_all(&temp_directory).with_context(|err| ErrorKind::Io {
+            reason: format!("{}", err),
+        })?;
+    }
     create_dir_all(temp_directory.clone())?;
-    set_current_dir(temp_directory.clone())?;
+
+    // The default toolchain is the one without a name.
+    let build_cache = options
+        .build_cache
+        .as_ref()
+        .map(|build_cache| parent_directory.join(build_cache));
+    let mut target_directories = HashMap::new();
+    if build_cache.is_some() || options.shared_target {
+        for toolchain in once(None).chain(toolchains.iter().cloned()) {
+            let toolchain_id = get_toolchain_id(toolchain.as_deref())?;
+            if let Some(target_directory) = get_target_directory(
+                build_cache.as_deref(),
+                options.shared_target,
+                &testing_directory,
+                &toolchain_id,
+            ) {
+                target_directories.insert(toolchain, target_directory);
+            }
+        }
+    }
 
     let tested_crates = crate_list.get_tested_crates_list();
-    let total_crates = tested_crates.len();
-    let pool = Pool::<ThunkWorker<Result<Crate>>>::new(options.threads);
+    let total_crates = tested_crates.len() + crate_list.get_failed_crates().len();
+    println!("Testing {} crates:", tested_crates.len());
+    for used_crate in tested_crates.iter() {
+        println!("    {}", used_crate);
+    }
+
+    // The lockfile exists at this point, because loading of the crate list
+    // generates it when needed.
+    let lockfile = match options.integration_lock {
+        true => find_lockfile(project_location.as_path()),
+        false => None,
+    };
+    let patches = match options.inject_patches {
+        true => Some(crate_list.get_patches().clone()),
+        false => None,
+    };
+    let patch_args = PassThroughArgs::new(
+        &patches
+            .as_ref()
+            .map(|patches| patches.get_config_args())
+            .unwrap_or_default(),
+    );
+    let cache = options
+        .cache_directory
+        .as_ref()
+        .map(|directory| CheckoutCache::new(&parent_directory.join(directory)));
+    let checkout_options = CheckoutOptions {
+        cache,
+        lockfile,
+        patches,
+    };
+    let time_limits = TimeLimits::new(options.timeout, options.deadline);
+    let retries = options.retries;
+    let pool = Pool::<ThunkWorker<Result<CrateReport>>>::new(options.threads);
     let (tx, rx) = channel();
     for used_crate in tested_crates.clone().into_iter() {
-        pool.execute_to(tx.clone(), Thunk::of(move || run_crate_tests(used_crate)));
+        let args = options.get_pass_through_args(&used_crate);
+        let configurations = with_toolchains(
+            with_modes(
+                get_test_configurations(&used_crate, options.feature_matrix, options.matrix_limit),
+                &options.modes,
+            ),
+            &toolchains,
+        )
+        .into_iter()
+        .map(|configuration| configuration.with_args(&patch_args).with_args(&args))
+        .map(
+            |configuration| match target_directories.get(&configuration.get_toolchain()) {
+                Some(directory) => configuration.with_target_directory(directory),
+                None => configuration,
+            },
+        )
+        .collect::<Vec<_>>();
+        let deps_directory = temp_directory.clone();
+        let checkout_options = checkout_options.clone();
+        pool.execute_to(
+            tx.clone(),
+            Thunk::of(move || {
+                run_crate_tests(
+                    used_crate,
+                    configurations,
+                    &deps_directory,
+                    time_limits,
+                    retries,
+                    checkout_options,
+                )
+            }),
+        );
     }
 
     rx.iter()
         .take(tested_crates.len())
-        .filter(|response| response.is_err())
-        .for_each(|response| {
-            let error = response.unwrap_err();
-            let error_kind = error.kind();
-            crate_list.append_error(error_kind);
+        .for_each(|response| match response {
+            Ok(report) => crate_list.append_report(report, options.strict_flaky),
+            Err(error) => crate_list.append_error(error.kind()),
         });
 
+    print_checkout_summary(crate_list.get_reports());
+    print_warnings(crate_list.get_reports());
+    print_flaky_crates(crate_list.get_reports());
+    if !target_directories.is_empty() {
+        print_build_summary(crate_list.get_reports());
+    }
+    if options.feature_matrix.is_some() || options.modes.len() > 1 {
+        print_feature_matrix(crate_list.get_reports());
+    }
+    if !toolchains.is_empty() {
+        print_toolchain_grid(crate_list.get_reports(), &toolchains);
+    }
+    if let Some(rust_version) = &rust_version {
+        print_msrv_violations(crate_list.get_tested_crates_list(), rust_version);
+    }
+
     match crate_list.has_failed_tests() {
         true => {
             let failed_crates = crate_list.get_failed_crates();
@@ -225,10 +1247,525 @@ pub fn test_crates(options: &TestOptions) -> Result<()> {
         false => println!("Well done! All crates work correctly."),
     }
 
-    set_current_dir(parent_directory)?;
-    let temp_parent_directory = temp_directory.parent().unwrap();
-    remove_dir_all(temp_parent_directory).with_context(|err| ErrorKind::Io {
+    let skipped_crates = crate_list.get_skipped_crates();
+    if !skipped_crates.is_empty() {
+        println!("Skipped {} crates:", skipped_crates.len());
+        for (skipped_crate, reason) in skipped_crates.iter() {
+            println!("    {} (skipped: {})", skipped_crate, reason);
+        }
+    }
+
+    clean_testing_directory(
+        &crate_list,
+        &testing_directory,
+        options.keep,
+        options.keep_failed,
+    )
+}
+
+// Removes the testing area at the end of a run. Kept checkouts are useful on
+// their own, while the shared target directory is removed anyway.
+fn clean_testing_directory(
+    crate_list: &CrateList,
+    testing_directory: &Path,
+    keep: bool,
+    keep_failed: bool,
+) -> Result<()> {
+    let deps_directory = testing_directory.join("deps");
+    if keep || keep_failed {
+        let shared_target_directory = testing_directory.join("target");
+        if shared_target_directory.exists() {
+            remove_dir_all(&shared_target_directory).with_context(|err| ErrorKind::Io {
+                reason: format!("{}", err),
+            })?;
+        }
+    }
+    if keep {
+        println!("Checkouts are kept in {}", deps_directory.display());
+        return Ok(());
+    }
+    if keep_failed && crate_list.has_failed_tests() {
+        keep_failed_checkouts(crate_list, &deps_directory)?;
+        return Ok(());
+    }
+    remove_dir_all(testing_directory).with_context(|err| ErrorKind::Io {
         reason: format!("{}", err),
     })?;
     Ok(())
 }
+
+// The build cache outlives the run, while the shared target directory is
+// removed with the rest of the testing area. Both are split by toolchains, so
+// builds with different compilers neither wait for each other on Cargo's lock
+// nor replace each other's artifacts.
+fn get_target_directory(
+    build_cache: Option<&Path>,
+    shared_target: bool,
+    testing_directory: &Path,
+    toolchain_id: &str,
+) -> Option<PathBuf> {
+    match (build_cache, shared_target) {
+        (Some(build_cache), _) => Some(build_cache.join(toolchain_id)),
+        (None, true) => Some(testing_directory.join("target").join(toolchain_id)),
+        (None, false) => None,
+    }
+}
+
+// The testing area of a project lives in the temporary directory, because Cargo
+// takes crates checked out inside of a project with a `[workspace]` table for
+// its members and refuses to build them.
+fn get_testing_directory(project_directory: &Path) -> PathBuf {
+    let name = project_directory
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    temp_dir()
+        .join("cargo-test-all")
+        .join(format!("{}-{}", name, short_hash(&project_directory)))
+}
+
+// Removes checkouts of the crates that passed, so only the failed ones (and
+// the ones that couldn't be checked out) remain in the testing area.
+fn keep_failed_checkouts(crate_list: &CrateList, deps_directory: &Path) -> Result<()> {
+    let passed_crates = crate_list
+        .get_reports()
+        .iter()
+        .filter(|report| !report.is_failed() && !report.is_timed_out())
+        .map(|report| report.get_crate().get_checkout_name())
+        .collect::<HashSet<String>>();
+
+    println!("Checkouts of the failed crates are kept in:");
+    for used_crate in crate_list.get_tested_crates_list().iter() {
+        let checkout_name = used_crate.get_checkout_name();
+        let directory = deps_directory.join(&checkout_name);
+        if !directory.exists() {
+            continue;
+        }
+        match passed_crates.contains(&checkout_name) {
+            true => {
+                remove_dir_all(&directory).with_context(|err| ErrorKind::Io {
+                    reason: format!("{}", err),
+                })?;
+            }
+            false => println!("    {}", directory.display()),
+        }
+    }
+    Ok(())
+}
+
+// Prunes the checkout cache by the time since each checkout was last used and
+// by the total size.
+pub fn clean_cache(
+    cache_directory: &Path,
+    max_age: Option<Duration>,
+    max_size: Option<u64>,
+) -> Result<()> {
+    let cache = CheckoutCache::new(&current_dir()?.join(cache_directory));
+    let (removed, size) = cache.clean(max_age, max_size)?;
+    println!(
+        "Removed {} checkouts ({:.1} MiB).",
+        removed,
+        size as f64 / (1 << 20) as f64
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+    use std::process;
+
+    use super::*;
+    use crate::report::{TestOutcome, TestStatusEnum};
+
+    // Metadata of the `app` project, where `edges` are written as `(package,
+    // dependency, kind, target)`. Packages other than `app` come from
+    // crates.io.
+    fn get_graph(packages: &[&str], edges: &[(&str, &str, &str, Option<&str>)]) -> DependencyGraph {
+        let package_entries = packages
+            .iter()
+            .map(|name| {
+                let source = match *name {
+                    "app" => String::from("null"),
+                    _ => format!("\"registry+{}\"", CRATES_IO_INDEX),
+                };
+                format!(
+                    r#"{{"id": "{0}", "name": "{0}", "version": "1.0.0", "source": {1},
+                        "manifest_path": "/{0}/Cargo.toml", "features": {{}}}}"#,
+                    name, source
+                )
+            })
+            .collect::<Vec<String>>();
+        let nodes = packages
+            .iter()
+            .map(|name| {
+                let deps = edges
+                    .iter()
+                    .filter(|(package, _, _, _)| package == name)
+                    .map(|(_, dependency, kind, target)| {
+                        let kind = match *kind {
+                            "normal" => String::from("null"),
+                            kind => format!("\"{}\"", kind),
+                        };
+                        let target = match target {
+                            Some(target) => format!("\"{}\"", target),
+                            None => String::from("null"),
+                        };
+                        format!(
+                            r#"{{"name": "{0}", "pkg": "{0}",
+                                "dep_kinds": [{{"kind": {1}, "target": {2}}}]}}"#,
+                            dependency, kind, target
+                        )
+                    })
+                    .collect::<Vec<String>>();
+                format!(
+                    r#"{{"id": "{}", "deps": [{}], "features": []}}"#,
+                    name,
+                    deps.join(", ")
+                )
+            })
+            .collect::<Vec<String>>();
+
+        DependencyGraph::parse(&format!(
+            r#"{{"packages": [{}], "workspace_members": ["app"], "workspace_root": "/app",
+                "resolve": {{"nodes": [{}]}}}}"#,
+            package_entries.join(", "),
+            nodes.join(", ")
+        ))
+        .unwrap()
+    }
+
+    fn get_names(crates: &[Crate]) -> Vec<String> {
+        let mut names = crates
+            .iter()
+            .map(|used_crate| used_crate.get_name())
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    fn get_chain_graph() -> DependencyGraph {
+        get_graph(
+            &["app", "a", "b", "c", "x"],
+            &[
+                ("app", "a", "normal", None),
+                ("app", "x", "normal", None),
+                ("a", "b", "normal", None),
+                ("x", "b", "normal", None),
+                ("b", "c", "normal", None),
+                ("c", "a", "normal", None),
+            ],
+        )
+    }
+
+    #[test]
+    fn transitive_crates_are_limited_by_depth() {
+        let graph = get_chain_graph();
+        let lockfile = Lockfile::default();
+
+        let crates = collect_transitive_crates(&graph, &lockfile, DepthEnum::Limited(1), "app");
+        assert!(crates.is_empty());
+        let crates = collect_transitive_crates(&graph, &lockfile, DepthEnum::Limited(2), "app");
+        assert_eq!(get_names(&crates), vec!["b"]);
+        let crates = collect_transitive_crates(&graph, &lockfile, DepthEnum::Limited(3), "app");
+        assert_eq!(get_names(&crates), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn transitive_crates_are_collected_once_despite_cycles() {
+        // The direct dependency `a`, reached again through the cycle, is
+        // tested as a direct one, so it isn't repeated.
+        let graph = get_chain_graph();
+        let crates = collect_transitive_crates(&graph, &Lockfile::default(), DepthEnum::All, "app");
+        assert_eq!(get_names(&crates), vec!["b", "c"]);
+    }
+
+    fn get_crate_list(crates: Vec<Crate>) -> CrateList {
+        CrateList {
+            all: Box::new(crates),
+            skipped: Vec::new(),
+            failed: Box::new(Vec::new()),
+            reports: Vec::new(),
+            rust_version: None,
+            patches: Patches::default(),
+        }
+    }
+
+    #[test]
+    fn transitive_crates_keep_kind_of_build_and_dev_parents() {
+        let graph = get_graph(
+            &["app", "a", "builder", "tester", "n", "bn", "dn", "shared"],
+            &[
+                ("app", "a", "normal", None),
+                ("app", "builder", "build", None),
+                ("app", "tester", "dev", None),
+                ("a", "n", "normal", None),
+                ("a", "shared", "normal", None),
+                ("builder", "bn", "normal", None),
+                ("tester", "dn", "normal", None),
+                ("tester", "shared", "normal", None),
+            ],
+        );
+        let crates = collect_transitive_crates(&graph, &Lockfile::default(), DepthEnum::All, "app");
+        let kinds = |name: &str| {
+            crates
+                .iter()
+                .find(|used_crate| used_crate.get_name() == name)
+                .unwrap()
+                .get_kinds()
+        };
+        assert_eq!(kinds("n"), vec![DependencyKindEnum::Normal]);
+        assert_eq!(kinds("bn"), vec![DependencyKindEnum::Build]);
+        assert_eq!(kinds("dn"), vec![DependencyKindEnum::Dev]);
+        assert_eq!(
+            kinds("shared"),
+            vec![DependencyKindEnum::Normal, DependencyKindEnum::Dev]
+        );
+
+        let crate_list =
+            get_crate_list(crates.clone()).with_filter_kinds(&[DependencyKindEnum::Normal]);
+        assert_eq!(
+            get_names(crate_list.get_tested_crates_list()),
+            vec!["n", "shared"]
+        );
+        let crate_list = get_crate_list(crates)
+            .with_filter_kinds(&[DependencyKindEnum::Build, DependencyKindEnum::Dev]);
+        assert_eq!(
+            get_names(crate_list.get_tested_crates_list()),
+            vec!["bn", "dn", "shared"]
+        );
+    }
+
+    #[test]
+    fn transitive_crates_combine_platforms_of_parent_and_edge() {
+        // Before the platforms were combined, the `cfg(unix)` edge replaced the
+        // `cfg(windows)` one of its parent, so `u` was tested on Unix.
+        let graph = get_graph(
+            &["app", "w", "u", "z"],
+            &[
+                ("app", "w", "normal", Some("cfg(windows)")),
+                ("w", "u", "normal", Some("cfg(unix)")),
+                ("u", "z", "normal", None),
+            ],
+        );
+        let crates = collect_transitive_crates(&graph, &Lockfile::default(), DepthEnum::All, "app");
+        let platforms = |name: &str| {
+            crates
+                .iter()
+                .find(|used_crate| used_crate.get_name() == name)
+                .unwrap()
+                .get_platforms()
+        };
+        let combined = Some(String::from("cfg(all(windows, unix))"));
+        assert_eq!(platforms("u"), vec![combined.clone()]);
+        assert_eq!(platforms("z"), vec![combined]);
+    }
+
+    fn get_registry_dependency(name: &str, requirement: &str) -> Dependency {
+        let source_id =
+            cargo::core::SourceId::for_registry(&CRATES_IO_INDEX.parse().unwrap()).unwrap();
+        Dependency::parse(name, Some(requirement), source_id).unwrap()
+    }
+
+    #[test]
+    fn resolved_packages_are_found_by_edges_of_graph() {
+        // Another member pins `rand` to 0.8.4, while 0.8.5 is locked as well,
+        // so the highest matching version isn't the one the member uses.
+        let graph = DependencyGraph::parse(&format!(
+            r#"{{"packages": [
+                {{"id": "app", "name": "app", "version": "0.1.0", "source": null,
+                  "manifest_path": "/app/Cargo.toml", "features": {{}}}},
+                {{"id": "rand-0.7.3", "name": "rand", "version": "0.7.3", "source": "{0}",
+                  "manifest_path": "/rand-0.7.3/Cargo.toml", "features": {{}}}},
+                {{"id": "rand-0.8.4", "name": "rand", "version": "0.8.4", "source": "{0}",
+                  "manifest_path": "/rand-0.8.4/Cargo.toml", "features": {{}}}},
+                {{"id": "rand-0.8.5", "name": "rand", "version": "0.8.5", "source": "{0}",
+                  "manifest_path": "/rand-0.8.5/Cargo.toml", "features": {{}}}},
+                {{"id": "foo-bar", "name": "foo-bar", "version": "1.0.0", "source": "{0}",
+                  "manifest_path": "/foo-bar/Cargo.toml", "features": {{}}}}
+            ], "workspace_members": ["app"], "workspace_root": "/app",
+            "resolve": {{"nodes": [{{"id": "app", "features": [], "deps": [
+                {{"name": "rand07", "pkg": "rand-0.7.3", "dep_kinds": [{{"kind": null}}]}},
+                {{"name": "rand", "pkg": "rand-0.8.4", "dep_kinds": [{{"kind": null}}]}},
+                {{"name": "foo_bar", "pkg": "foo-bar", "dep_kinds": [{{"kind": null}}]}}
+            ]}}]}}}}"#,
+            format_args!("registry+{}", CRATES_IO_INDEX)
+        ))
+        .unwrap();
+        let find = |dependency: &Dependency| {
+            find_resolved_package(&graph, "app", dependency).map(|package| package.get_id())
+        };
+
+        let dependency = get_registry_dependency("rand", "0.8");
+        assert_eq!(find(&dependency), Some(String::from("rand-0.8.4")));
+        let mut dependency = get_registry_dependency("rand", "0.7");
+        dependency.set_explicit_name_in_toml("rand07");
+        assert_eq!(find(&dependency), Some(String::from("rand-0.7.3")));
+        let dependency = get_registry_dependency("foo-bar", "1");
+        assert_eq!(find(&dependency), Some(String::from("foo-bar")));
+
+        let source_id = cargo::core::SourceId::for_path(Path::new("/rand")).unwrap();
+        let dependency = Dependency::parse("rand", None, source_id).unwrap();
+        assert_eq!(find(&dependency), None);
+    }
+
+    #[test]
+    fn target_directories_are_split_by_toolchains() {
+        let testing_directory = Path::new("/tmp/testing");
+        let get = |build_cache: Option<&Path>, shared_target: bool, toolchain_id: &str| {
+            get_target_directory(build_cache, shared_target, testing_directory, toolchain_id)
+        };
+
+        assert_eq!(get(None, false, "1.60.0"), None);
+        assert_eq!(
+            get(None, true, "1.60.0"),
+            Some(PathBuf::from("/tmp/testing/target/1.60.0"))
+        );
+        assert_eq!(
+            get(None, true, "1.56.1"),
+            Some(PathBuf::from("/tmp/testing/target/1.56.1"))
+        );
+        assert_eq!(
+            get(Some(Path::new("/cache")), true, "1.60.0"),
+            Some(PathBuf::from("/cache/1.60.0"))
+        );
+        assert_eq!(
+            get(Some(Path::new("/cache")), false, "1.56.1"),
+            Some(PathBuf::from("/cache/1.56.1"))
+        );
+    }
+
+    #[test]
+    fn unused_patches_fall_back_to_original_dependencies() {
+        let root = temp_dir().join(format!("cargo-test-all-command-{}-patch", process::id()));
+        create_dir_all(root.join("src")).unwrap();
+        write(root.join("src/lib.rs"), "").unwrap();
+        write(
+            root.join("Cargo.toml"),
+            "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n[patch.crates-io]\n\
+             rand = { git = \"https://github.com/forks/rand\" }\n",
+        )
+        .unwrap();
+        let patches = Patches::load(&root).unwrap();
+        remove_dir_all(&root).unwrap();
+
+        let get_graph = |source: &str| {
+            DependencyGraph::parse(&format!(
+                r#"{{"packages": [
+                    {{"id": "app", "name": "app", "version": "0.1.0", "source": null,
+                      "manifest_path": "/app/Cargo.toml", "features": {{}}}},
+                    {{"id": "rand", "name": "rand", "version": "0.8.5", "source": "{}",
+                      "manifest_path": "/rand/Cargo.toml", "features": {{}}}}
+                ], "workspace_members": ["app"], "workspace_root": "/app",
+                "resolve": {{"nodes": [{{"id": "app", "features": [], "deps": [
+                    {{"name": "rand", "pkg": "rand", "dep_kinds": [{{"kind": null}}]}}
+                ]}}]}}}}"#,
+                source
+            ))
+            .unwrap()
+        };
+        let dependency = get_registry_dependency("rand", "0.8");
+
+        let graph = get_graph("git+https://github.com/forks/rand#0123abc");
+        let (resolved, package) = resolve_dependency(&graph, "app", &patches, &dependency);
+        assert!(resolved.source_id().is_git());
+        assert!(package.is_some());
+
+        let graph = get_graph(&format!("registry+{}", CRATES_IO_INDEX));
+        let (resolved, package) = resolve_dependency(&graph, "app", &patches, &dependency);
+        assert!(resolved.source_id().is_default_registry());
+        assert!(package.is_some());
+    }
+
+    #[test]
+    fn depth_is_parsed_from_number_or_all() {
+        assert_eq!(DepthEnum::from_str("2"), Ok(DepthEnum::Limited(2)));
+        assert_eq!(DepthEnum::from_str("all"), Ok(DepthEnum::All));
+        assert!(DepthEnum::from_str("0").is_err());
+        assert!(DepthEnum::from_str("deep").is_err());
+    }
+
+    fn create_testing_directory(name: &str, checkouts: &[String]) -> PathBuf {
+        let testing_directory =
+            temp_dir().join(format!("cargo-test-all-command-{}-{}", process::id(), name));
+        for checkout in checkouts.iter() {
+            create_dir_all(testing_directory.join("deps").join(checkout)).unwrap();
+        }
+        create_dir_all(testing_directory.join("target").join("1.60.0")).unwrap();
+        testing_directory
+    }
+
+    fn get_tested_crate_list() -> CrateList {
+        let crates = vec![
+            Crate::new(&get_registry_dependency("rand", "0.8"), "0.8.5", None, None),
+            Crate::new(&get_registry_dependency("serde", "1"), "1.0.0", None, None),
+        ];
+        let mut crate_list = get_crate_list(crates.clone());
+        let mut passed = CrateReport::new(&crates[0]);
+        passed.add_outcome(TestOutcome::new("default", TestStatusEnum::Passed, ""));
+        let mut failed = CrateReport::new(&crates[1]);
+        failed.add_outcome(TestOutcome::new("default", TestStatusEnum::Failed, ""));
+        crate_list.reports = vec![passed, failed];
+        crate_list.failed.push(ErrorKind::TestsFailure {
+            crate_name: crates[1].get_name(),
+            output: String::new(),
+        });
+        crate_list
+    }
+
+    fn get_checkout_names(crate_list: &CrateList) -> Vec<String> {
+        crate_list
+            .get_tested_crates_list()
+            .iter()
+            .map(|used_crate| used_crate.get_checkout_name())
+            .collect()
+    }
+
+    #[test]
+    fn testing_directory_is_removed_without_keep_flags() {
+        let crate_list = get_tested_crate_list();
+        let testing_directory = create_testing_directory("clean", &get_checkout_names(&crate_list));
+
+        clean_testing_directory(&crate_list, &testing_directory, false, false).unwrap();
+        assert!(!testing_directory.exists());
+    }
+
+    #[test]
+    fn keep_leaves_checkouts_and_removes_shared_target() {
+        let crate_list = get_tested_crate_list();
+        let checkouts = get_checkout_names(&crate_list);
+        let testing_directory = create_testing_directory("keep", &checkouts);
+
+        clean_testing_directory(&crate_list, &testing_directory, true, false).unwrap();
+        for checkout in checkouts.iter() {
+            assert!(testing_directory.join("deps").join(checkout).exists());
+        }
+        assert!(!testing_directory.join("target").exists());
+        remove_dir_all(&testing_directory).unwrap();
+    }
+
+    #[test]
+    fn keep_failed_leaves_only_failed_checkouts() {
+        let crate_list = get_tested_crate_list();
+        let checkouts = get_checkout_names(&crate_list);
+        let testing_directory = create_testing_directory("keep-failed", &checkouts);
+
+        clean_testing_directory(&crate_list, &testing_directory, false, true).unwrap();
+        assert!(!testing_directory.join("deps").join(&checkouts[0]).exists());
+        assert!(testing_directory.join("deps").join(&checkouts[1]).exists());
+        assert!(!testing_directory.join("target").exists());
+        remove_dir_all(&testing_directory).unwrap();
+    }
+
+    #[test]
+    fn keep_failed_removes_everything_when_crates_pass() {
+        let mut crate_list = get_tested_crate_list();
+        crate_list.failed.clear();
+        crate_list.reports.truncate(1);
+        let testing_directory =
+            create_testing_directory("keep-passed", &get_checkout_names(&crate_list));
+
+        clean_testing_directory(&crate_list, &testing_directory, false, true).unwrap();
+        assert!(!testing_directory.exists());
+    }
+}
@@ -49,6 +49,65 @@ pub enum ErrorKind {
         crate_name, output
     )]
     TestsFailure { crate_name: String, output: String },
+    #[fail(
+        display = "Tests for the {} crate timed out. Output: \n{}",
+        crate_name, output
+    )]
+    TestsTimeout { crate_name: String, output: String },
+    #[fail(
+        display = "Tests for the {} crate are flaky, they passed only after rerunning: {}",
+        crate_name, tests
+    )]
+    FlakyTests { crate_name: String, tests: String },
+    #[fail(display = "The command timed out.")]
+    Timeout { output: String },
+    #[fail(
+        display = "The {} crate is missing in Cargo.lock. Run `cargo update` and try again.",
+        crate_name
+    )]
+    MissingLockedPackage { crate_name: String },
+    #[fail(display = "The {} package isn't a member of the workspace.", package)]
+    UnknownPackage { package: String },
+    #[fail(display = "The {} crate isn't used by the project.{}", selector, hint)]
+    UnknownCrate { selector: String, hint: String },
+    #[fail(
+        display = "The {} crate isn't available in the vendored sources. Run `cargo vendor` and try again.",
+        crate_name
+    )]
+    NotVendored { crate_name: String },
+    #[fail(
+        display = "The vendored {} crate is modified: the checksum of {} doesn't match.",
+        crate_name, path
+    )]
+    ChecksumMismatch { crate_name: String, path: String },
+    #[fail(
+        display = "The {} crate is checked out at {}, but {} is locked in Cargo.lock.",
+        crate_name, actual, expected
+    )]
+    RevisionMismatch {
+        crate_name: String,
+        expected: String,
+        actual: String,
+    },
+    #[fail(
+        display = "The {} package isn't found in the {} repository.",
+        crate_name, url
+    )]
+    PackageNotFound { crate_name: String, url: String },
+    #[fail(
+        display = "The {} toolchains aren't installed. Run `rustup toolchain install` for them and try again.",
+        toolchains
+    )]
+    MissingToolchains { toolchains: String },
+    #[fail(
+        display = "The project doesn't declare `rust-version` in Cargo.toml, so there is no MSRV to check."
+    )]
+    MissingRustVersion,
+    #[fail(
+        display = "The workspace at {} is outside of the checkout at {}, so its Cargo.lock isn't changed.",
+        workspace, checkout
+    )]
+    WorkspaceOutsideCheckout { workspace: String, checkout: String },
     #[fail(display = "{}", description)]
     Other { description: String },
 }
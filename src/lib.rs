@@ -1,11 +1,21 @@
+pub mod cache;
 pub mod cli;
 pub mod command;
 pub mod error;
+pub mod lockfile;
+pub mod matrix;
+pub mod metadata;
+pub mod patch;
+pub mod platform;
+pub mod process;
+pub mod report;
 pub mod runners;
+pub mod selector;
 pub mod util;
+pub mod vendor;
 pub mod worker;
 
 pub use crate::runners::{
     get_test_runner, CratesIoDependencyTestRunner, GitDependencyTestRunner,
-    LocalDependencyTestRunner, TestRunner,
+    LocalDependencyTestRunner, TestRunner, VendoredDependencyTestRunner,
 };
@@ -0,0 +1,347 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use cargo::core::{Dependency, SourceId};
+use cargo::util::ToSemver;
+use failure::ResultExt;
+use toml::Value;
+
+use crate::error::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone)]
+pub struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    checksum: Option<String>,
+}
+
+impl LockedPackage {
+    pub fn get_version(&self) -> String {
+        self.version.clone()
+    }
+
+    pub fn get_checksum(&self) -> Option<String> {
+        self.checksum.clone()
+    }
+
+    pub fn get_source(&self) -> Option<String> {
+        self.source.clone()
+    }
+
+    pub fn get_locked_commit(&self) -> Option<String> {
+        self.source.as_deref().and_then(get_locked_commit)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Lockfile {
+    packages: Vec<LockedPackage>,
+}
+
+impl Lockfile {
+    pub fn load(project_location: &Path) -> Result<Self> {
+        let path = match find_lockfile(project_location) {
+            Some(path) => path,
+            None => {
+                generate_lockfile(project_location)?;
+                find_lockfile(project_location).ok_or_else(|| {
+                    Error::from(ErrorKind::Other {
+                        description: String::from("Can't find the generated Cargo.lock file."),
+                    })
+                })?
+            }
+        };
+
+        Lockfile::parse(&read_to_string(&path)?)
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let lockfile = content.parse::<Value>().with_context(|err| ErrorKind::Io {
+            reason: format!("Can't read Cargo.lock file. Reason: {}", err),
+        })?;
+
+        let packages = lockfile
+            .get("package")
+            .and_then(|value| value.as_array())
+            .map(|packages| {
+                packages
+                    .iter()
+                    .filter_map(|package| parse_locked_package(package, &lockfile))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Lockfile { packages })
+    }
+
+    // Looks for the package that the resolver picked for the dependency. When
+    // a few versions of the same crate are locked, the highest one that still
+    // satisfies the requirement wins, the same way as Cargo does.
+    pub fn find_dependency(&self, dependency: &Dependency) -> Option<&LockedPackage> {
+        let name = dependency.package_name().to_string();
+        let source_id = dependency.source_id();
+
+        self.packages
+            .iter()
+            .filter(|package| package.name == name)
+            .filter(|package| matches_source(package.source.as_deref(), source_id))
+            .filter_map(|package| {
+                package
+                    .version
+                    .to_semver()
+                    .ok()
+                    .filter(|version| dependency.version_req().matches(version))
+                    .map(|version| (version, package))
+            })
+            .max_by(|(left, _), (right, _)| left.cmp(right))
+            .map(|(_, package)| package)
+    }
+
+    // Specifications of the locked versions of the package for `cargo update
+    // --package`. The plain name is ambiguous only when a few versions are
+    // locked, and it's the only form that older versions of Cargo accept.
+    pub fn get_package_specs(&self, name: &str) -> Vec<String> {
+        let versions = self
+            .packages
+            .iter()
+            .filter(|package| package.name == name)
+            .map(|package| package.version.clone())
+            .collect::<Vec<String>>();
+        match versions.len() {
+            1 => vec![name.to_string()],
+            _ => versions
+                .iter()
+                .map(|version| format!("{}@{}", name, version))
+                .collect(),
+        }
+    }
+
+    pub fn find_package(
+        &self,
+        name: &str,
+        version: &str,
+        source: Option<&str>,
+    ) -> Option<&LockedPackage> {
+        self.packages.iter().find(|package| {
+            package.name == name
+                && package.version == version
+                && package.source.as_deref() == source
+        })
+    }
+}
+
+// Git sources are locked as `git+<url>?<reference>#<commit>`.
+pub fn get_locked_commit(source: &str) -> Option<String> {
+    source
+        .strip_prefix("git+")
+        .and_then(|source| source.split_once('#'))
+        .map(|(_, commit)| commit.to_string())
+}
+
+// Compares a source written the way Cargo.lock and `cargo metadata` do it with
+// the source of a dependency. Only the URL matters, without the kind prefix
+// (`registry+`, `git+`), the git query and the fragment with the locked
+// commit. Path dependencies have no source at all.
+pub fn matches_source(source: Option<&str>, source_id: SourceId) -> bool {
+    match source {
+        Some(source) => {
+            let url = match source.find('+') {
+                Some(index) => &source[index + 1..],
+                None => source,
+            };
+            let url = url.split('#').next().unwrap_or(url);
+            let url = url.split('?').next().unwrap_or(url);
+            !source_id.is_path()
+                && url.trim_end_matches('/') == source_id.url().as_str().trim_end_matches('/')
+        }
+        None => source_id.is_path(),
+    }
+}
+
+pub fn find_lockfile(project_location: &Path) -> Option<PathBuf> {
+    project_location
+        .ancestors()
+        .map(|directory| directory.join("Cargo.lock"))
+        .find(|path| path.is_file())
+}
+
+fn generate_lockfile(project_location: &Path) -> Result<()> {
+    let output = Command::new("cargo")
+        .arg("generate-lockfile")
+        .current_dir(project_location)
+        .output()
+        .context(ErrorKind::InvalidCommand {
+            description: String::from("Can't execute the `cargo generate-lockfile` command."),
+        })?;
+
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(Error::from(ErrorKind::InvalidCommand {
+            description: String::from_utf8_lossy(&output.stderr).to_string(),
+        })),
+    }
+}
+
+fn parse_locked_package(package: &Value, lockfile: &Value) -> Option<LockedPackage> {
+    let name = package.get("name")?.as_str()?.to_string();
+    let version = package.get("version")?.as_str()?.to_string();
+    let source = package
+        .get("source")
+        .and_then(|value| value.as_str())
+        .map(|value| value.to_string());
+
+    // Lockfiles of the first version keep checksums in the separate
+    // [metadata] table instead of the package entries.
+    let checksum = match package.get("checksum").and_then(|value| value.as_str()) {
+        Some(checksum) => Some(checksum.to_string()),
+        None => source.as_ref().and_then(|source| {
+            let key = format!("checksum {} {} ({})", name, version, source);
+            lockfile
+                .get("metadata")
+                .and_then(|metadata| metadata.get(&key))
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string())
+        }),
+    };
+
+    Some(LockedPackage {
+        name,
+        version,
+        source,
+        checksum,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cargo::sources::CRATES_IO_INDEX;
+
+    use super::*;
+
+    const LOCKFILE: &str = r#"
+version = 3
+
+[[package]]
+name = "project"
+version = "0.1.0"
+
+[[package]]
+name = "local"
+version = "0.2.0"
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "aaaa"
+
+[[package]]
+name = "rand"
+version = "0.8.4"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "bbbb"
+
+[[package]]
+name = "rand"
+version = "0.8.5"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "cccc"
+
+[[package]]
+name = "serde"
+version = "1.0.130"
+source = "git+https://github.com/serde-rs/serde?branch=master#0123456789abcdef"
+"#;
+
+    fn get_registry_dependency(name: &str, requirement: &str) -> Dependency {
+        let source_id = SourceId::for_registry(&CRATES_IO_INDEX.parse().unwrap()).unwrap();
+        Dependency::parse(name, Some(requirement), source_id).unwrap()
+    }
+
+    #[test]
+    fn find_dependency_picks_highest_matching_version() {
+        let lockfile = Lockfile::parse(LOCKFILE).unwrap();
+
+        let locked = lockfile
+            .find_dependency(&get_registry_dependency("rand", "0.8"))
+            .unwrap();
+        assert_eq!(locked.get_version(), "0.8.5");
+        assert_eq!(locked.get_checksum(), Some(String::from("cccc")));
+
+        let locked = lockfile
+            .find_dependency(&get_registry_dependency("rand", "0.7"))
+            .unwrap();
+        assert_eq!(locked.get_version(), "0.7.3");
+
+        let locked = lockfile
+            .find_dependency(&get_registry_dependency("rand", "=0.8.4"))
+            .unwrap();
+        assert_eq!(locked.get_version(), "0.8.4");
+        assert!(lockfile
+            .find_dependency(&get_registry_dependency("rand", "0.6"))
+            .is_none());
+    }
+
+    #[test]
+    fn find_dependency_compares_sources() {
+        let lockfile = Lockfile::parse(LOCKFILE).unwrap();
+        assert!(lockfile
+            .find_dependency(&get_registry_dependency("serde", "1"))
+            .is_none());
+
+        let git_url = "https://github.com/serde-rs/serde".parse().unwrap();
+        let source_id =
+            SourceId::for_git(&git_url, cargo::core::GitReference::DefaultBranch).unwrap();
+        let dependency = Dependency::parse("serde", Some("1"), source_id).unwrap();
+        let locked = lockfile.find_dependency(&dependency).unwrap();
+        assert_eq!(
+            locked.get_locked_commit(),
+            Some(String::from("0123456789abcdef"))
+        );
+
+        let source_id = SourceId::for_path(Path::new("/projects/local")).unwrap();
+        let dependency = Dependency::parse("local", None, source_id).unwrap();
+        let locked = lockfile.find_dependency(&dependency).unwrap();
+        assert_eq!(locked.get_version(), "0.2.0");
+        assert_eq!(locked.get_locked_commit(), None);
+    }
+
+    #[test]
+    fn package_specs_have_versions_only_when_ambiguous() {
+        let lockfile = Lockfile::parse(LOCKFILE).unwrap();
+        assert_eq!(
+            lockfile.get_package_specs("rand"),
+            vec!["rand@0.7.3", "rand@0.8.4", "rand@0.8.5"]
+        );
+        assert_eq!(lockfile.get_package_specs("serde"), vec!["serde"]);
+    }
+
+    #[test]
+    fn parse_reads_checksums_of_first_lockfile_version() {
+        let lockfile = Lockfile::parse(
+            r#"
+[[package]]
+name = "libc"
+version = "0.2.60"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[metadata]
+"checksum libc 0.2.60 (registry+https://github.com/rust-lang/crates.io-index)" = "dddd"
+"#,
+        )
+        .unwrap();
+
+        let locked = lockfile
+            .find_package(
+                "libc",
+                "0.2.60",
+                Some("registry+https://github.com/rust-lang/crates.io-index"),
+            )
+            .unwrap();
+        assert_eq!(locked.get_checksum(), Some(String::from("dddd")));
+        assert_eq!(lockfile.get_package_specs("libc"), vec!["libc"]);
+        assert!(lockfile.get_package_specs("rand").is_empty());
+    }
+}
@@ -1,30 +1,36 @@
+mod cache;
 mod cli;
 mod command;
 mod error;
+mod lockfile;
+mod matrix;
+mod metadata;
+mod patch;
+mod platform;
+mod process;
+mod report;
 pub mod runners;
+mod selector;
 mod util;
+mod vendor;
 mod worker;
 
-use structopt::StructOpt;
+use std::env;
 
-use crate::cli::CliOptions;
-use crate::command::{test_crates, TestOptions};
+use crate::cli::{CliOptions, CommandEnum};
+use crate::command::{clean_cache, test_crates, TestOptions};
 
 fn main() {
-    let args = CliOptions::from_args();
-    let test_only = args
-        .only
-        .unwrap_or(String::from(""))
-        .split(",")
-        .map(|s| s.to_string().trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
-
-    let options = TestOptions {
-        threads: args.threads,
-        test_only,
+    let mut args = CliOptions::from_cargo_args(env::args());
+    let result = match args.command.take() {
+        Some(CommandEnum::Clean {
+            cache_dir,
+            older_than,
+            max_size,
+        }) => clean_cache(&cache_dir, older_than, max_size),
+        None => test_crates(&TestOptions::from(args)),
     };
-    match test_crates(&options) {
+    match result {
         Err(err) => println!("{}", err),
         _ => (),
     }
@@ -0,0 +1,547 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::command::Crate;
+use crate::selector::CrateSelector;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureMatrixEnum {
+    Each,
+    Powerset,
+}
+
+impl FromStr for FeatureMatrixEnum {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "each" => Ok(FeatureMatrixEnum::Each),
+            "powerset" => Ok(FeatureMatrixEnum::Powerset),
+            _ => Err(format!(
+                "Expected `each` or `powerset`, but got `{}`.",
+                value
+            )),
+        }
+    }
+}
+
+// What is done with every crate: from a fast `check` to running the tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestModeEnum {
+    Check,
+    Build,
+    Test,
+    Lib,
+    DocTests,
+    Doc,
+    Bench,
+}
+
+impl TestModeEnum {
+    pub fn get_command(&self) -> &'static str {
+        match self {
+            TestModeEnum::Check => "check",
+            TestModeEnum::Build => "build",
+            TestModeEnum::Test | TestModeEnum::Lib | TestModeEnum::DocTests => "test",
+            TestModeEnum::Doc => "doc",
+            TestModeEnum::Bench => "bench",
+        }
+    }
+
+    // Only these modes run test binaries, the others just build the crate.
+    pub fn is_running_tests(&self) -> bool {
+        matches!(
+            self,
+            TestModeEnum::Test | TestModeEnum::Lib | TestModeEnum::DocTests
+        )
+    }
+
+    pub fn get_args(&self) -> Vec<String> {
+        let args: &[&str] = match self {
+            TestModeEnum::Build => &["--tests"],
+            TestModeEnum::Lib => &["--lib"],
+            TestModeEnum::DocTests => &["--doc"],
+            TestModeEnum::Doc => &["--no-deps"],
+            TestModeEnum::Bench => &["--no-run"],
+            _ => &[],
+        };
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+}
+
+impl FromStr for TestModeEnum {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "check" => Ok(TestModeEnum::Check),
+            "build" => Ok(TestModeEnum::Build),
+            "test" => Ok(TestModeEnum::Test),
+            "lib" => Ok(TestModeEnum::Lib),
+            "doctest" => Ok(TestModeEnum::DocTests),
+            "doc" => Ok(TestModeEnum::Doc),
+            "bench" => Ok(TestModeEnum::Bench),
+            _ => Err(format!(
+                "Expected one of `check`, `build`, `test`, `lib`, `doctest`, `doc` or `bench`, but got `{}`.",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TestModeEnum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut command = vec![self.get_command().to_string()];
+        command.extend(self.get_args());
+        write!(f, "{}", command.join(" "))
+    }
+}
+
+// Arguments passed through to cargo and, after another `--`, to the test
+// harness, like `--release -- --test-threads=1`.
+#[derive(Debug, Clone, Default)]
+pub struct PassThroughArgs {
+    cargo_args: Vec<String>,
+    harness_args: Vec<String>,
+}
+
+impl PassThroughArgs {
+    pub fn new(args: &[String]) -> Self {
+        match args.iter().position(|arg| arg == "--") {
+            Some(index) => PassThroughArgs {
+                cargo_args: args[..index].to_vec(),
+                harness_args: args[index + 1..].to_vec(),
+            },
+            None => PassThroughArgs {
+                cargo_args: args.to_vec(),
+                harness_args: Vec::new(),
+            },
+        }
+    }
+}
+
+// Pass-through arguments used instead of the common ones for the crates
+// matched by the selector, written as `<selector>=<arguments>`.
+#[derive(Debug, Clone)]
+pub struct CrateArgs {
+    selector: CrateSelector,
+    args: PassThroughArgs,
+}
+
+impl CrateArgs {
+    pub fn matches(&self, used_crate: &Crate) -> bool {
+        self.selector.matches(used_crate)
+    }
+
+    pub fn get_args(&self) -> PassThroughArgs {
+        self.args.clone()
+    }
+}
+
+impl FromStr for CrateArgs {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (selector, args) = value.split_once('=').ok_or_else(|| {
+            format!(
+                "Expected arguments like `serde=--release -- --test-threads=1`, but got `{}`.",
+                value
+            )
+        })?;
+        let args = split_shell_words(args)?;
+
+        Ok(CrateArgs {
+            selector: CrateSelector::from_str(selector)?,
+            args: PassThroughArgs::new(&args),
+        })
+    }
+}
+
+// Splits arguments the way a POSIX shell does: by whitespace, except inside
+// quotes, and with backslashes escaping the next symbol.
+fn split_shell_words(value: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut symbols = value.chars();
+    while let Some(symbol) = symbols.next() {
+        match symbol {
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match symbols.next() {
+                        Some('\'') => break,
+                        Some(symbol) => word.push(symbol),
+                        None => return Err(format!("Unclosed quote in `{}`.", value)),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match symbols.next() {
+                        Some('"') => break,
+                        Some('\\') => match symbols.next() {
+                            Some(symbol) if "\"\\$`".contains(symbol) => word.push(symbol),
+                            Some(symbol) => {
+                                word.push('\\');
+                                word.push(symbol);
+                            }
+                            None => return Err(format!("Unclosed quote in `{}`.", value)),
+                        },
+                        Some(symbol) => word.push(symbol),
+                        None => return Err(format!("Unclosed quote in `{}`.", value)),
+                    }
+                }
+            }
+            '\\' => {
+                let word = word.get_or_insert_with(String::new);
+                match symbols.next() {
+                    Some(symbol) => word.push(symbol),
+                    None => return Err(format!("Nothing to escape at the end of `{}`.", value)),
+                }
+            }
+            symbol if symbol.is_whitespace() => words.extend(word.take()),
+            symbol => word.get_or_insert_with(String::new).push(symbol),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[derive(Debug, Clone)]
+pub struct TestConfiguration {
+    name: String,
+    mode: TestModeEnum,
+    toolchain: Option<String>,
+    cargo_args: Vec<String>,
+    harness_args: Vec<String>,
+    envs: Vec<(String, String)>,
+}
+
+impl TestConfiguration {
+    pub fn new(name: &str, cargo_args: Vec<String>) -> Self {
+        TestConfiguration {
+            name: name.to_string(),
+            mode: TestModeEnum::Test,
+            toolchain: None,
+            cargo_args,
+            harness_args: Vec::new(),
+            envs: Vec::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_command(&self) -> String {
+        self.mode.get_command().to_string()
+    }
+
+    pub fn get_toolchain(&self) -> Option<String> {
+        self.toolchain.clone()
+    }
+
+    pub fn get_mode(&self) -> TestModeEnum {
+        self.mode
+    }
+
+    // Arguments of the test harness are passed only when tests are run,
+    // because the other cargo commands don't accept them.
+    pub fn get_cargo_args(&self) -> Vec<String> {
+        let mut cargo_args = self.mode.get_args();
+        cargo_args.extend(self.cargo_args.iter().cloned());
+        if self.mode.is_running_tests() && !self.harness_args.is_empty() {
+            cargo_args.push(String::from("--"));
+            cargo_args.extend(self.harness_args.iter().cloned());
+        }
+        cargo_args
+    }
+
+    // The same configuration in another mode. When a few modes are tested,
+    // the mode is mentioned in the name to keep their results apart.
+    pub fn with_mode(&self, mode: TestModeEnum, is_named: bool) -> Self {
+        let mut configuration = self.clone();
+        configuration.mode = mode;
+        if is_named {
+            configuration.name = format!("{}: {}", mode, self.name);
+        }
+        configuration
+    }
+
+    // Environment variables for the cargo command, like `CARGO_TARGET_DIR`.
+    pub fn get_envs(&self) -> Vec<(String, String)> {
+        self.envs.clone()
+    }
+
+    // The same configuration for the toolchain, installed with rustup.
+    pub fn with_toolchain(&self, toolchain: &str) -> Self {
+        let mut configuration = self.clone();
+        configuration.toolchain = Some(toolchain.to_string());
+        configuration.name = format!("+{}: {}", toolchain, self.name);
+        configuration
+    }
+
+    // Builds the crate in the shared target directory. Cargo takes a lock on
+    // the directory, so concurrent builds wait for each other instead of
+    // corrupting artifacts, while tests of built crates still run in parallel.
+    // The verbose output lists reused (`Fresh`) units for the build report.
+    pub fn with_target_directory(self, directory: &Path) -> Self {
+        self.with_env("CARGO_TARGET_DIR", &directory.to_string_lossy())
+            .with_env("CARGO_TERM_VERBOSE", "true")
+    }
+
+    pub fn with_args(mut self, args: &PassThroughArgs) -> Self {
+        self.cargo_args.extend(args.cargo_args.iter().cloned());
+        self.harness_args.extend(args.harness_args.iter().cloned());
+        self
+    }
+
+    pub fn with_env(mut self, key: &str, value: &str) -> Self {
+        self.envs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    // Cargo stops at the first failed test binary, so failed test cases
+    // of the later binaries wouldn't be known without the flag.
+    pub fn with_no_fail_fast(&self) -> Self {
+        let mut configuration = self.clone();
+        let flag = String::from("--no-fail-fast");
+        if self.mode.is_running_tests() && !configuration.cargo_args.contains(&flag) {
+            configuration.cargo_args.push(flag);
+        }
+        configuration
+    }
+
+    // The same configuration, that runs only the given test cases.
+    pub fn with_test_filters(&self, tests: &[String]) -> Self {
+        let mut configuration = self.clone();
+        configuration.harness_args.push(String::from("--exact"));
+        configuration.harness_args.extend(tests.iter().cloned());
+        configuration
+    }
+
+    fn with_features(features: &[&String]) -> Self {
+        let features = features
+            .iter()
+            .map(|feature| feature.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        let mut cargo_args = vec![String::from("--no-default-features")];
+        if !features.is_empty() {
+            cargo_args.push(String::from("--features"));
+            cargo_args.push(features.clone());
+        }
+
+        match features.is_empty() {
+            true => TestConfiguration::new("no-default-features", cargo_args),
+            false => TestConfiguration::new(&format!("features: {}", features), cargo_args),
+        }
+    }
+}
+
+// The first configuration is always the feature set used by the project,
+// while the rest are added only in the feature-matrix mode. Every one of
+// them runs against the same checkout of the crate.
+pub fn get_test_configurations(
+    used_crate: &Crate,
+    feature_matrix: Option<FeatureMatrixEnum>,
+    limit: usize,
+) -> Vec<TestConfiguration> {
+    let mut configurations = vec![TestConfiguration::new(
+        "project",
+        used_crate.get_cargo_features_args(),
+    )];
+
+    let available_features = used_crate.get_available_features();
+    let features = available_features
+        .iter()
+        .filter(|feature| feature.as_str() != "default")
+        .collect::<Vec<&String>>();
+
+    match feature_matrix {
+        Some(FeatureMatrixEnum::Each) => {
+            configurations.push(TestConfiguration::with_features(&[]));
+            configurations.push(TestConfiguration::new("default", Vec::new()));
+            configurations.push(TestConfiguration::new(
+                "all-features",
+                vec![String::from("--all-features")],
+            ));
+            for feature in features.iter() {
+                configurations.push(TestConfiguration::with_features(&[feature]));
+            }
+        }
+        Some(FeatureMatrixEnum::Powerset) => {
+            let mut powerset = vec![TestConfiguration::new("default", Vec::new())];
+            'sizes: for size in 0..=features.len() {
+                for combination in get_combinations(&features, size, limit) {
+                    if powerset.len() >= limit {
+                        break 'sizes;
+                    }
+                    powerset.push(TestConfiguration::with_features(&combination));
+                }
+            }
+            configurations.extend(powerset);
+        }
+        None => (),
+    };
+
+    configurations
+}
+
+// Repeats every configuration for each of the modes.
+pub fn with_modes(
+    configurations: Vec<TestConfiguration>,
+    modes: &[TestModeEnum],
+) -> Vec<TestConfiguration> {
+    modes
+        .iter()
+        .flat_map(|mode| {
+            configurations
+                .iter()
+                .map(move |configuration| configuration.with_mode(*mode, modes.len() > 1))
+        })
+        .collect()
+}
+
+// Repeats every configuration for each of the toolchains, where `None` is
+// the default one. Without toolchains only the default one is used.
+pub fn with_toolchains(
+    configurations: Vec<TestConfiguration>,
+    toolchains: &[Option<String>],
+) -> Vec<TestConfiguration> {
+    match toolchains.is_empty() {
+        true => configurations,
+        false => toolchains
+            .iter()
+            .flat_map(|toolchain| {
+                configurations
+                    .iter()
+                    .map(move |configuration| match toolchain {
+                        Some(toolchain) => configuration.with_toolchain(toolchain),
+                        None => configuration.clone(),
+                    })
+            })
+            .collect(),
+    }
+}
+
+// Generates up to `limit` combinations of the given size in the
+// lexicographic order, so the smallest feature sets are checked first.
+fn get_combinations<'a>(
+    features: &[&'a String],
+    size: usize,
+    limit: usize,
+) -> Vec<Vec<&'a String>> {
+    let mut combinations = Vec::new();
+    if size > features.len() {
+        return combinations;
+    }
+
+    let mut indices = (0..size).collect::<Vec<usize>>();
+    loop {
+        combinations.push(indices.iter().map(|index| features[*index]).collect());
+        if combinations.len() >= limit {
+            break;
+        }
+
+        let position = (0..size)
+            .rev()
+            .find(|position| indices[*position] != position + features.len() - size);
+        match position {
+            Some(position) => {
+                indices[position] += 1;
+                for next in position + 1..size {
+                    indices[next] = indices[next - 1] + 1;
+                }
+            }
+            None => break,
+        }
+    }
+
+    combinations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_words(value: &str) -> Vec<String> {
+        split_shell_words(value).unwrap()
+    }
+
+    #[test]
+    fn split_shell_words_by_whitespace() {
+        assert_eq!(
+            get_words("  --release   --lib "),
+            vec!["--release", "--lib"]
+        );
+        assert!(get_words("").is_empty());
+    }
+
+    #[test]
+    fn split_shell_words_keeps_quoted_spaces() {
+        assert_eq!(
+            get_words(r#"--features "a b" -- 'tests::with space' x\ y"#),
+            vec!["--features", "a b", "--", "tests::with space", "x y"]
+        );
+        assert_eq!(get_words(r#"--cfg="a\"b" ''"#), vec![r#"--cfg=a"b"#, ""]);
+    }
+
+    #[test]
+    fn split_shell_words_rejects_unclosed_quotes() {
+        assert!(split_shell_words(r#"--features "a b"#).is_err());
+        assert!(split_shell_words("'a").is_err());
+        assert!(split_shell_words("a\\").is_err());
+    }
+
+    #[test]
+    fn crate_args_split_cargo_and_harness_args() {
+        let crate_args = CrateArgs::from_str(r#"serde=--features "a b" -- --test-threads=1"#)
+            .unwrap()
+            .get_args();
+        assert_eq!(crate_args.cargo_args, vec!["--features", "a b"]);
+        assert_eq!(crate_args.harness_args, vec!["--test-threads=1"]);
+        assert!(CrateArgs::from_str("serde").is_err());
+    }
+
+    #[test]
+    fn with_toolchains_keeps_default_toolchain() {
+        let configurations = vec![TestConfiguration::new("default", Vec::new())];
+        let toolchains = vec![None, Some(String::from("1.56"))];
+        let configurations = with_toolchains(configurations, &toolchains);
+
+        assert_eq!(configurations.len(), 2);
+        assert_eq!(configurations[0].get_toolchain(), None);
+        assert_eq!(configurations[0].get_name(), "default");
+        assert_eq!(
+            configurations[1].get_toolchain(),
+            Some(String::from("1.56"))
+        );
+        assert_eq!(configurations[1].get_name(), "+1.56: default");
+    }
+
+    #[test]
+    fn no_fail_fast_is_added_only_when_running_tests() {
+        let configuration = TestConfiguration::new("default", Vec::new());
+        assert_eq!(
+            configuration.with_no_fail_fast().get_cargo_args(),
+            vec!["--no-fail-fast"]
+        );
+        assert_eq!(
+            configuration
+                .with_no_fail_fast()
+                .with_no_fail_fast()
+                .get_cargo_args(),
+            vec!["--no-fail-fast"]
+        );
+        assert_eq!(
+            configuration
+                .with_mode(TestModeEnum::Check, false)
+                .with_no_fail_fast()
+                .get_cargo_args(),
+            Vec::<String>::new()
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+use failure::ResultExt;
+use rustc_serialize::json::Json;
+
+use crate::command::DependencyKindEnum;
+use crate::error::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone)]
+pub struct MetadataPackage {
+    id: String,
+    name: String,
+    version: String,
+    source: Option<String>,
+    manifest_path: String,
+    features: Vec<String>,
+    rust_version: Option<String>,
+}
+
+impl MetadataPackage {
+    pub fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_version(&self) -> String {
+        self.version.clone()
+    }
+
+    pub fn get_source(&self) -> Option<String> {
+        self.source.clone()
+    }
+
+    pub fn get_features(&self) -> Vec<String> {
+        self.features.clone()
+    }
+
+    // The minimal Rust version declared by the package as `rust-version`.
+    pub fn get_rust_version(&self) -> Option<String> {
+        self.rust_version.clone()
+    }
+
+    pub fn get_directory(&self) -> String {
+        Path::new(&self.manifest_path)
+            .parent()
+            .unwrap_or_else(|| Path::new("/"))
+            .to_str()
+            .unwrap_or("")
+            .to_string()
+    }
+}
+
+pub type DependencyEdge = (DependencyKindEnum, Option<String>);
+
+#[derive(Debug, Clone)]
+struct NodeDependency {
+    id: String,
+    name: String,
+    kinds: Vec<DependencyEdge>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    workspace_root: String,
+    workspace_members: Vec<String>,
+    packages: HashMap<String, MetadataPackage>,
+    edges: HashMap<String, Vec<NodeDependency>>,
+    features: HashMap<String, Vec<String>>,
+}
+
+impl DependencyGraph {
+    pub fn load(project_location: &Path, feature_args: &[String]) -> Result<Self> {
+        let output = Command::new("cargo")
+            .arg("metadata")
+            .arg("--format-version")
+            .arg("1")
+            .args(feature_args)
+            .current_dir(project_location)
+            .output()
+            .context(ErrorKind::InvalidCommand {
+                description: String::from("Can't execute the `cargo metadata` command."),
+            })?;
+
+        if !output.status.success() {
+            return Err(Error::from(ErrorKind::InvalidCommand {
+                description: String::from_utf8_lossy(&output.stderr).to_string(),
+            }));
+        }
+
+        let response =
+            String::from_utf8(output.stdout.clone()).with_context(|err| ErrorKind::Utf8 {
+                value: output.stdout,
+                index: err.clone().utf8_error().valid_up_to(),
+            })?;
+
+        DependencyGraph::parse(&response)
+    }
+
+    pub fn parse(response: &str) -> Result<Self> {
+        let json = Json::from_str(response).context(ErrorKind::Other {
+            description: String::from("Can't parse a response from the `cargo metadata` command."),
+        })?;
+
+        let packages = json
+            .find("packages")
+            .and_then(|packages| packages.as_array())
+            .map(|packages| {
+                packages
+                    .iter()
+                    .filter_map(parse_package)
+                    .map(|package| (package.id.clone(), package))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let workspace_root = json
+            .find("workspace_root")
+            .and_then(|value| value.as_string())
+            .unwrap_or("")
+            .to_string();
+        let workspace_members = json
+            .find("workspace_members")
+            .and_then(|members| members.as_array())
+            .map(|members| {
+                members
+                    .iter()
+                    .filter_map(|member| member.as_string())
+                    .map(|member| member.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let nodes = json
+            .find("resolve")
+            .filter(|resolve| !resolve.is_null())
+            .and_then(|resolve| resolve.find("nodes"))
+            .and_then(|nodes| nodes.as_array())
+            .map(|nodes| nodes.iter().filter_map(parse_node).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let mut edges = HashMap::new();
+        let mut features = HashMap::new();
+        for (id, dependencies, node_features) in nodes {
+            edges.insert(id.clone(), dependencies);
+            features.insert(id, node_features);
+        }
+
+        Ok(DependencyGraph {
+            workspace_root,
+            workspace_members,
+            packages,
+            edges,
+            features,
+        })
+    }
+
+    pub fn get_workspace_root(&self) -> String {
+        self.workspace_root.clone()
+    }
+
+    pub fn get_workspace_members(&self) -> Vec<&MetadataPackage> {
+        self.workspace_members
+            .iter()
+            .filter_map(|id| self.packages.get(id))
+            .collect()
+    }
+
+    pub fn get_features(&self, id: &str) -> Vec<String> {
+        self.features.get(id).cloned().unwrap_or_default()
+    }
+
+    pub fn is_workspace_member(&self, id: &str) -> bool {
+        self.workspace_members.iter().any(|member| member == id)
+    }
+
+    // Returns the dependencies of the package along with the kinds and the
+    // platforms (`cfg(...)` expressions or target triples) they are used for.
+    pub fn get_dependencies(&self, id: &str) -> Vec<(&MetadataPackage, Vec<DependencyEdge>)> {
+        self.edges
+            .get(id)
+            .map(|dependencies| {
+                dependencies
+                    .iter()
+                    .filter_map(|dependency| {
+                        self.packages
+                            .get(&dependency.id)
+                            .map(|package| (package, dependency.kinds.clone()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Returns the dependencies of the package along with the names they are
+    // known by in its code: the key in Cargo.toml for the renamed ones and the
+    // name of the library target for the rest.
+    pub fn get_dependency_names(&self, id: &str) -> Vec<(&MetadataPackage, String)> {
+        self.edges
+            .get(id)
+            .map(|dependencies| {
+                dependencies
+                    .iter()
+                    .filter_map(|dependency| {
+                        self.packages
+                            .get(&dependency.id)
+                            .map(|package| (package, dependency.name.clone()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+fn parse_package(package: &Json) -> Option<MetadataPackage> {
+    Some(MetadataPackage {
+        id: package.find("id")?.as_string()?.to_string(),
+        name: package.find("name")?.as_string()?.to_string(),
+        version: package.find("version")?.as_string()?.to_string(),
+        source: package
+            .find("source")
+            .and_then(|value| value.as_string())
+            .map(|value| value.to_string()),
+        manifest_path: package.find("manifest_path")?.as_string()?.to_string(),
+        features: package
+            .find("features")
+            .and_then(|features| features.as_object())
+            .map(|features| features.keys().cloned().collect())
+            .unwrap_or_default(),
+        rust_version: package
+            .find("rust_version")
+            .and_then(|value| value.as_string())
+            .map(|value| value.to_string()),
+    })
+}
+
+fn parse_node(node: &Json) -> Option<(String, Vec<NodeDependency>, Vec<String>)> {
+    let id = node.find("id")?.as_string()?.to_string();
+    let dependencies = node
+        .find("deps")
+        .and_then(|deps| deps.as_array())
+        .map(|deps| deps.iter().filter_map(parse_node_dependency).collect())
+        .unwrap_or_default();
+    let features = node
+        .find("features")
+        .and_then(|features| features.as_array())
+        .map(|features| {
+            features
+                .iter()
+                .filter_map(|feature| feature.as_string())
+                .map(|feature| feature.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Some((id, dependencies, features))
+}
+
+fn parse_node_dependency(dependency: &Json) -> Option<NodeDependency> {
+    let id = dependency.find("pkg")?.as_string()?.to_string();
+    let name = dependency.find("name")?.as_string()?.to_string();
+    let mut kinds = Vec::new();
+    for dependency_kind in dependency.find("dep_kinds")?.as_array()?.iter() {
+        let kind = match dependency_kind
+            .find("kind")
+            .and_then(|kind| kind.as_string())
+        {
+            Some("build") => DependencyKindEnum::Build,
+            Some("dev") => DependencyKindEnum::Dev,
+            _ => DependencyKindEnum::Normal,
+        };
+        let target = dependency_kind
+            .find("target")
+            .and_then(|target| target.as_string())
+            .map(|target| target.to_string());
+        if !kinds.contains(&(kind, target.clone())) {
+            kinds.push((kind, target));
+        }
+    }
+
+    Some(NodeDependency { id, name, kinds })
+}
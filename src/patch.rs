@@ -0,0 +1,365 @@
+use std::path::Path;
+
+use cargo::core::{Dependency, EitherManifest, GitReference, SourceId};
+use cargo::sources::CRATES_IO_INDEX;
+use cargo::util::toml::read_manifest;
+use cargo::Config;
+use failure::ResultExt;
+use toml::Value;
+
+use crate::command::Crate;
+use crate::error::{Error, ErrorKind, Result};
+
+// A package from the `[patch]` or `[replace]` section of the workspace
+// manifest, that the project uses instead of the original one.
+#[derive(Debug, Clone)]
+struct PatchedPackage {
+    name: String,
+    source_url: String,
+    version: Option<String>,
+    replacement: Replacement,
+}
+
+// The source of the patch, without the rest of its dependency declaration.
+#[derive(Debug, Clone)]
+struct Replacement {
+    package_name: String,
+    name_in_toml: String,
+    source_id: SourceId,
+}
+
+impl From<&Dependency> for Replacement {
+    fn from(dependency: &Dependency) -> Self {
+        Replacement {
+            package_name: dependency.package_name().to_string(),
+            name_in_toml: dependency.name_in_toml().to_string(),
+            source_id: dependency.source_id(),
+        }
+    }
+}
+
+impl PatchedPackage {
+    fn matches(&self, dependency: &Dependency) -> bool {
+        let source_url = dependency.source_id().url().to_string();
+        let is_version_matched = match &self.version {
+            Some(version) => version
+                .parse()
+                .map(|version| dependency.version_req().matches(&version))
+                .unwrap_or(false),
+            None => true,
+        };
+
+        self.name == dependency.package_name().as_str()
+            && self.source_url == source_url.trim_end_matches('/')
+            && is_version_matched
+    }
+
+    // The patch as `--config` arguments for cargo, one for each field, like
+    // `patch.crates-io.serde.path="/forks/serde"`. Only patches with path
+    // and Git sources can be added to other workspaces.
+    fn get_config_args(&self) -> Vec<String> {
+        let source_id = self.replacement.source_id;
+        let mut fields = Vec::new();
+        if source_id.is_path() {
+            let path = source_id.url().to_file_path().unwrap_or_default();
+            fields.push(("path", path.to_string_lossy().to_string()));
+        } else if source_id.is_git() {
+            fields.push(("git", source_id.url().to_string()));
+            match source_id.git_reference() {
+                Some(GitReference::Branch(branch)) => fields.push(("branch", branch.to_owned())),
+                Some(GitReference::Tag(tag)) => fields.push(("tag", tag.to_owned())),
+                Some(GitReference::Rev(rev)) => fields.push(("rev", rev.to_owned())),
+                _ => (),
+            }
+        } else {
+            return Vec::new();
+        }
+        if self.replacement.package_name != self.replacement.name_in_toml {
+            fields.push(("package", self.replacement.package_name.clone()));
+        }
+
+        let registry = match self.source_url.as_str() {
+            CRATES_IO_INDEX => String::from("crates-io"),
+            url => Value::String(url.to_string()).to_string(),
+        };
+        fields
+            .iter()
+            .flat_map(|(key, value)| {
+                vec![
+                    String::from("--config"),
+                    format!(
+                        "patch.{}.{}.{}={}",
+                        registry,
+                        self.replacement.name_in_toml,
+                        key,
+                        Value::String(value.to_owned())
+                    ),
+                ]
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Patches {
+    packages: Vec<PatchedPackage>,
+}
+
+impl Patches {
+    // Patches are declared only in the manifest of the workspace root, which
+    // can be a virtual one.
+    pub fn load(workspace_location: &Path) -> Result<Self> {
+        let config = Config::default().expect("Unable to get config.");
+        let cargo_toml_path = workspace_location.join("Cargo.toml");
+        let source_id = SourceId::for_path(workspace_location).map_err(|err| {
+            Error::from(ErrorKind::Io {
+                reason: format!(
+                    "Can't generate SourceId for Cargo.toml file. Reason: {:?}",
+                    err
+                ),
+            })
+        })?;
+        let (manifest, _path) =
+            read_manifest(&cargo_toml_path, source_id, &config).with_context(|err| {
+                ErrorKind::Io {
+                    reason: format!("Can't read Cargo.toml file. Reason: {:?}", err),
+                }
+            })?;
+
+        let (patch, replace) = match &manifest {
+            EitherManifest::Real(manifest) => (manifest.patch(), manifest.replace()),
+            EitherManifest::Virtual(manifest) => (manifest.patch(), manifest.replace()),
+        };
+
+        let mut packages = Vec::new();
+        for (url, dependencies) in patch.iter() {
+            let source_url = url.to_string();
+            for dependency in dependencies.iter() {
+                packages.push(PatchedPackage {
+                    name: dependency.package_name().to_string(),
+                    source_url: source_url.trim_end_matches('/').to_string(),
+                    version: None,
+                    replacement: Replacement::from(dependency),
+                });
+            }
+        }
+        for (spec, dependency) in replace.iter() {
+            let source_url = spec
+                .url()
+                .map(|url| url.to_string())
+                .unwrap_or_else(|| CRATES_IO_INDEX.to_string());
+            packages.push(PatchedPackage {
+                name: spec.name().to_string(),
+                source_url: source_url.trim_end_matches('/').to_string(),
+                version: spec.version().map(|version| version.to_string()),
+                replacement: Replacement::from(dependency),
+            });
+        }
+
+        packages.sort_by(|left, right| left.name.cmp(&right.name));
+        Ok(Patches { packages })
+    }
+
+    // The dependency with the sources of the patches and replacements that
+    // can apply to it. The version requirement stays the same, because Cargo
+    // uses a patch only when its version matches the requirement, which is
+    // known only after resolving. Unused patches are listed by Cargo.lock as
+    // `[[patch.unused]]`, and the original source is used instead.
+    pub fn apply(&self, dependency: &Dependency) -> Vec<Dependency> {
+        self.packages
+            .iter()
+            .filter(|package| package.matches(dependency))
+            .map(|package| {
+                let mut patched_dependency = dependency.clone();
+                patched_dependency.set_source_id(package.replacement.source_id);
+                patched_dependency
+            })
+            .collect()
+    }
+
+    // Whether the crate comes from the source of some patch or replacement.
+    pub fn is_patched(&self, used_crate: &Crate) -> bool {
+        self.packages.iter().any(|package| {
+            let source_id = package.replacement.source_id;
+            let path = match source_id.is_path() {
+                true => source_id
+                    .url()
+                    .to_file_path()
+                    .map(|path| path.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                false => source_id.url().to_string(),
+            };
+            package.replacement.package_name == used_crate.get_name()
+                && path == used_crate.get_path()
+        })
+    }
+
+    pub fn get_names(&self) -> Vec<String> {
+        let mut names = self
+            .packages
+            .iter()
+            .map(|package| package.name.clone())
+            .collect::<Vec<String>>();
+        names.dedup();
+        names
+    }
+
+    // Arguments for cargo, that apply the same patches in the workspaces of
+    // the tested crates. Replacements are added as patches, because cargo
+    // doesn't support `[replace]` in its configuration.
+    pub fn get_config_args(&self) -> Vec<String> {
+        self.packages
+            .iter()
+            .flat_map(|package| package.get_config_args())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::*;
+
+    const MANIFEST: &str = r#"
+[package]
+name = "app"
+version = "0.1.0"
+
+[patch.crates-io]
+serde = { path = "forks/serde" }
+rand07 = { git = "https://github.com/rust-random/rand", branch = "0.7", package = "rand" }
+
+[patch."https://github.com/org/repo"]
+dep = { path = "forks/dep" }
+"#;
+
+    // Cargo doesn't allow `[replace]` along with `[patch]`.
+    const REPLACE_MANIFEST: &str = r#"
+[package]
+name = "app"
+version = "0.1.0"
+
+[replace]
+"libc:0.2.60" = { git = "https://github.com/rust-lang/libc", rev = "0123abc" }
+"#;
+
+    fn create_workspace(name: &str, manifest: &str) -> PathBuf {
+        let root = temp_dir().join(format!("cargo-test-all-patch-{}-{}", process::id(), name));
+        create_dir_all(root.join("src")).unwrap();
+        write(root.join("src/lib.rs"), "").unwrap();
+        write(root.join("Cargo.toml"), manifest).unwrap();
+        root
+    }
+
+    fn get_registry_dependency(name: &str, requirement: &str) -> Dependency {
+        let source_id = SourceId::for_registry(&CRATES_IO_INDEX.parse().unwrap()).unwrap();
+        Dependency::parse(name, Some(requirement), source_id).unwrap()
+    }
+
+    fn get_sources(dependencies: &[Dependency]) -> Vec<String> {
+        dependencies
+            .iter()
+            .map(|dependency| dependency.source_id().url().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn load_reads_patches_and_replacements() {
+        let root = create_workspace("load", MANIFEST);
+        let patches = Patches::load(&root).unwrap();
+        assert_eq!(patches.get_names(), vec!["dep", "rand", "serde"]);
+        remove_dir_all(&root).unwrap();
+
+        let root = create_workspace("load-replace", REPLACE_MANIFEST);
+        let patches = Patches::load(&root).unwrap();
+        assert_eq!(patches.get_names(), vec!["libc"]);
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn apply_lists_sources_of_matching_patches() {
+        let root = create_workspace("apply", MANIFEST);
+        let patches = Patches::load(&root).unwrap();
+
+        let serde = patches.apply(&get_registry_dependency("serde", "1"));
+        assert_eq!(
+            get_sources(&serde),
+            vec![format!("file://{}", root.join("forks/serde").display())]
+        );
+        // Patches don't declare versions, so only the resolver can tell
+        // whether the patch is used.
+        let rand = patches.apply(&get_registry_dependency("rand", "0.8"));
+        assert_eq!(
+            get_sources(&rand),
+            vec!["https://github.com/rust-random/rand"]
+        );
+        assert_eq!(rand[0].version_req().to_string(), "^0.8");
+
+        let git_url = "https://github.com/org/other".parse().unwrap();
+        let source_id = SourceId::for_git(&git_url, GitReference::DefaultBranch).unwrap();
+        let dependency = Dependency::parse("dep", None, source_id).unwrap();
+        assert!(patches.apply(&dependency).is_empty());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn apply_matches_versions_of_replacements() {
+        let root = create_workspace("apply-replace", REPLACE_MANIFEST);
+        let patches = Patches::load(&root).unwrap();
+
+        let libc = patches.apply(&get_registry_dependency("libc", "0.2"));
+        assert_eq!(
+            get_sources(&libc),
+            vec!["https://github.com/rust-lang/libc"]
+        );
+        assert!(patches
+            .apply(&get_registry_dependency("libc", "0.3"))
+            .is_empty());
+        assert_eq!(
+            patches.get_config_args(),
+            vec![
+                "--config",
+                "patch.crates-io.libc.git=\"https://github.com/rust-lang/libc\"",
+                "--config",
+                "patch.crates-io.libc.rev=\"0123abc\"",
+            ]
+        );
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn config_args_declare_patches_for_other_workspaces() {
+        let root = create_workspace("config", MANIFEST);
+        let patches = Patches::load(&root).unwrap();
+        let args = patches.get_config_args();
+
+        assert!(args.iter().step_by(2).all(|arg| arg == "--config"));
+        let values = args
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .cloned()
+            .collect::<Vec<String>>();
+        assert_eq!(
+            values,
+            vec![
+                format!(
+                    "patch.\"https://github.com/org/repo\".dep.path=\"{}\"",
+                    root.join("forks/dep").display()
+                ),
+                String::from("patch.crates-io.rand07.git=\"https://github.com/rust-random/rand\""),
+                String::from("patch.crates-io.rand07.branch=\"0.7\""),
+                String::from("patch.crates-io.rand07.package=\"rand\""),
+                format!(
+                    "patch.crates-io.serde.path=\"{}\"",
+                    root.join("forks/serde").display()
+                ),
+            ]
+        );
+        remove_dir_all(&root).unwrap();
+    }
+}
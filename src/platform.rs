@@ -0,0 +1,231 @@
+use std::cmp::Ordering;
+use std::process::Command;
+use std::str::FromStr;
+
+use cargo_platform::{Cfg, Platform};
+use failure::ResultExt;
+
+use crate::error::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone)]
+pub struct TargetPlatform {
+    triple: String,
+    cfgs: Vec<Cfg>,
+}
+
+impl TargetPlatform {
+    // Uses the same `cfg` values as rustc reports for the target, so that
+    // expressions in `[target.'cfg(...)'.dependencies]` tables are evaluated
+    // the way Cargo does it during the build.
+    pub fn load(target: Option<&str>) -> Result<Self> {
+        let triple = match target {
+            Some(triple) => triple.to_string(),
+            None => get_host_triple()?,
+        };
+
+        let output = run_rustc(&["--print", "cfg", "--target", &triple])?;
+        let mut cfgs = output
+            .lines()
+            .filter_map(|line| Cfg::from_str(line).ok())
+            .collect::<Vec<Cfg>>();
+        cfgs.push(Cfg::KeyPair(String::from("target"), triple.clone()));
+
+        Ok(TargetPlatform { triple, cfgs })
+    }
+
+    pub fn matches(&self, platform: &str) -> bool {
+        match Platform::from_str(platform) {
+            Ok(platform) => platform.matches(&self.triple, &self.cfgs),
+            Err(_) => true,
+        }
+    }
+}
+
+// The platform of a dependency pulled in by a platform-specific one, where
+// both conditions have to hold. Target triples are written as the `target`
+// key, which `TargetPlatform` adds to the `cfg` values of rustc.
+pub fn combine_platforms(parent: &str, edge: &str) -> String {
+    if parent == edge {
+        return parent.to_string();
+    }
+    format!(
+        "cfg(all({}, {}))",
+        get_cfg_expression(parent),
+        get_cfg_expression(edge)
+    )
+}
+
+fn get_cfg_expression(platform: &str) -> String {
+    match platform
+        .strip_prefix("cfg(")
+        .and_then(|platform| platform.strip_suffix(')'))
+    {
+        Some(expression) => expression.to_string(),
+        None => format!("target = \"{}\"", platform),
+    }
+}
+
+// Identifies the compiler by its release and commit, like `1.60.0-7737e0b5c`,
+// so that artifacts built by different toolchains are kept apart. Toolchains
+// other than the default one are selected via rustup.
+pub fn get_toolchain_id(toolchain: Option<&str>) -> Result<String> {
+    let toolchain = toolchain.map(|toolchain| format!("+{}", toolchain));
+    let args = toolchain
+        .iter()
+        .map(|toolchain| toolchain.as_str())
+        .chain(vec!["-vV"])
+        .collect::<Vec<&str>>();
+    let output = run_rustc(&args)?;
+    let get_value = |key: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(key))
+            .map(|value| value.trim().to_string())
+    };
+
+    let release = get_value("release: ").ok_or_else(|| {
+        Error::from(ErrorKind::Other {
+            description: String::from("Can't determine the version of rustc."),
+        })
+    })?;
+    match get_value("commit-hash: ") {
+        Some(hash) if hash != "unknown" => {
+            Ok(format!("{}-{}", release, &hash[..hash.len().min(9)]))
+        }
+        _ => Ok(release),
+    }
+}
+
+// Makes sure that all toolchains are installed before testing, so missing
+// ones are reported at once instead of failing every crate.
+pub fn check_toolchains(toolchains: &[String]) -> Result<()> {
+    let missing_toolchains = toolchains
+        .iter()
+        .filter(|toolchain| get_toolchain_id(Some(toolchain)).is_err())
+        .cloned()
+        .collect::<Vec<String>>();
+
+    match missing_toolchains.is_empty() {
+        true => Ok(()),
+        false => Err(Error::from(ErrorKind::MissingToolchains {
+            toolchains: missing_toolchains.join(", "),
+        })),
+    }
+}
+
+// Compares Rust versions like `1.56` and `1.56.1`, where missing parts are
+// treated as zeros. Only leading digits of each part are compared, so the
+// suffixes like `-nightly` are ignored.
+pub fn compare_rust_versions(left: &str, right: &str) -> Ordering {
+    let parse = |version: &str| {
+        let mut parts = version
+            .split('.')
+            .map(|part| {
+                part.trim()
+                    .chars()
+                    .take_while(|symbol| symbol.is_ascii_digit())
+                    .collect::<String>()
+                    .parse::<u64>()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<u64>>();
+        parts.resize(3, 0);
+        parts
+    };
+    parse(left).cmp(&parse(right))
+}
+
+fn get_host_triple() -> Result<String> {
+    let output = run_rustc(&["-vV"])?;
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(|triple| triple.trim().to_string())
+        .ok_or_else(|| {
+            Error::from(ErrorKind::Other {
+                description: String::from("Can't determine the host target triple."),
+            })
+        })
+}
+
+// Rustup is not allowed to install missing toolchains on the fly, so they
+// are reported instead of being downloaded silently.
+fn run_rustc(args: &[&str]) -> Result<String> {
+    let output = Command::new("rustc")
+        .args(args)
+        .env("RUSTUP_AUTO_INSTALL", "0")
+        .output()
+        .context(ErrorKind::InvalidCommand {
+            description: String::from("Can't execute the `rustc` command."),
+        })?;
+
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+        false => Err(Error::from(ErrorKind::InvalidCommand {
+            description: String::from_utf8_lossy(&output.stderr).to_string(),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_platform(triple: &str, cfgs: &[&str]) -> TargetPlatform {
+        let mut cfgs = cfgs
+            .iter()
+            .map(|cfg| Cfg::from_str(cfg).unwrap())
+            .collect::<Vec<Cfg>>();
+        cfgs.push(Cfg::KeyPair(String::from("target"), triple.to_string()));
+        TargetPlatform {
+            triple: triple.to_string(),
+            cfgs,
+        }
+    }
+
+    #[test]
+    fn compare_rust_versions_fills_missing_parts() {
+        assert_eq!(compare_rust_versions("1.56", "1.56.1"), Ordering::Less);
+        assert_eq!(compare_rust_versions("1.56", "1.56.0"), Ordering::Equal);
+        assert_eq!(compare_rust_versions("1.60", "1.56.1"), Ordering::Greater);
+        assert_eq!(compare_rust_versions("1.9", "1.10"), Ordering::Less);
+    }
+
+    #[test]
+    fn compare_rust_versions_ignores_non_numeric_parts() {
+        assert_eq!(
+            compare_rust_versions("1.70-beta", "1.70.0"),
+            Ordering::Equal
+        );
+        assert_eq!(
+            compare_rust_versions("1.56.1-nightly", "1.56.1"),
+            Ordering::Equal
+        );
+        assert_eq!(compare_rust_versions(" 1.65 ", "1.65.0"), Ordering::Equal);
+        assert_eq!(compare_rust_versions("1.x", "1.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn combine_platforms_requires_both_conditions() {
+        let platform = combine_platforms("cfg(windows)", "cfg(unix)");
+        assert_eq!(platform, "cfg(all(windows, unix))");
+        assert!(!get_platform("x86_64-unknown-linux-gnu", &["unix"]).matches(&platform));
+        assert!(!get_platform("x86_64-pc-windows-msvc", &["windows"]).matches(&platform));
+    }
+
+    #[test]
+    fn combine_platforms_keeps_same_condition() {
+        assert_eq!(combine_platforms("cfg(unix)", "cfg(unix)"), "cfg(unix)");
+    }
+
+    #[test]
+    fn combine_platforms_with_target_triple() {
+        let platform = combine_platforms("x86_64-unknown-linux-gnu", "cfg(unix)");
+        assert_eq!(
+            platform,
+            "cfg(all(target = \"x86_64-unknown-linux-gnu\", unix))"
+        );
+        assert!(get_platform("x86_64-unknown-linux-gnu", &["unix"]).matches(&platform));
+        assert!(!get_platform("aarch64-unknown-linux-gnu", &["unix"]).matches(&platform));
+    }
+}
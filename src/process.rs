@@ -0,0 +1,175 @@
+use std::io::Read;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use failure::ResultExt;
+
+use crate::error::{Error, ErrorKind, Result};
+
+// How often a running command is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// How long the output of a finished command is read. Processes that escaped
+// the process group can hold the pipes open for much longer.
+const READ_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+// Limits for testing: `timeout` for each crate and `deadline` for the run.
+// Limits too far in the future to be represented are the same as none.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeLimits {
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl TimeLimits {
+    pub fn new(timeout: Option<Duration>, run_duration: Option<Duration>) -> Self {
+        TimeLimits {
+            timeout,
+            deadline: run_duration.and_then(|duration| Instant::now().checked_add(duration)),
+        }
+    }
+
+    // The moment when testing of the crate started at `started` has to stop.
+    pub fn get_crate_deadline(&self, started: Instant) -> Option<Instant> {
+        let crate_deadline = self
+            .timeout
+            .and_then(|timeout| started.checked_add(timeout));
+        match (crate_deadline, self.deadline) {
+            (Some(left), Some(right)) => Some(left.min(right)),
+            (left, right) => left.or(right),
+        }
+    }
+}
+
+pub fn is_expired(deadline: Option<Instant>) -> bool {
+    deadline
+        .map(|deadline| Instant::now() >= deadline)
+        .unwrap_or(false)
+}
+
+// Runs the command the same way as `Command::output`, but stops it on the
+// deadline. The command is started in its own process group, so that test
+// binaries spawned by `cargo` are killed together with it.
+pub fn run_command(command: &mut Command, deadline: Option<Instant>) -> Result<Output> {
+    set_process_group(command);
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|err| ErrorKind::Io {
+            reason: format!("{}", err),
+        })?;
+
+    let stdout = Arc::new(Mutex::new(Vec::new()));
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+    let readers = vec![
+        read_stream(child.stdout.take(), stdout.clone()),
+        read_stream(child.stderr.take(), stderr.clone()),
+    ];
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if is_expired(deadline) {
+            kill_process_group(&mut child);
+            child.wait()?;
+            break None;
+        }
+        sleep(POLL_INTERVAL);
+    };
+
+    // Readers that are still blocked after the grace period are left behind,
+    // along with the output they haven't read yet.
+    let grace_deadline = Instant::now() + READ_GRACE_PERIOD;
+    while !readers.iter().all(|reader| reader.is_finished()) && !is_expired(Some(grace_deadline)) {
+        sleep(POLL_INTERVAL);
+    }
+    readers
+        .into_iter()
+        .filter(|reader| reader.is_finished())
+        .for_each(|reader| {
+            let _ = reader.join();
+        });
+    let stdout = stdout.lock().unwrap().clone();
+    let stderr = stderr.lock().unwrap().clone();
+
+    match status {
+        Some(status) => Ok(Output {
+            status,
+            stdout,
+            stderr,
+        }),
+        None => Err(Error::from(ErrorKind::Timeout {
+            output: format!(
+                "{}{}",
+                String::from_utf8_lossy(&stdout),
+                String::from_utf8_lossy(&stderr)
+            ),
+        })),
+    }
+}
+
+fn read_stream<R: Read + Send + 'static>(
+    stream: Option<R>,
+    buffer: Arc<Mutex<Vec<u8>>>,
+) -> JoinHandle<()> {
+    spawn(move || {
+        if let Some(mut stream) = stream {
+            let mut chunk = [0; 4096];
+            while let Ok(size) = stream.read(&mut chunk) {
+                if size == 0 {
+                    break;
+                }
+                buffer.lock().unwrap().extend_from_slice(&chunk[..size]);
+            }
+        }
+    })
+}
+
+#[cfg(unix)]
+fn set_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    command.process_group(0);
+}
+
+#[cfg(not(unix))]
+fn set_process_group(_command: &mut Command) {}
+
+#[cfg(unix)]
+fn kill_process_group(child: &mut Child) {
+    // The group id is the same as the id of its leader, started above.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(child: &mut Child) {
+    let _ = child.kill();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_command_stops_on_deadline_despite_escaped_processes() {
+        // The background `sleep` leaves the process group of the killed shell,
+        // but keeps its output pipes open.
+        let started = Instant::now();
+        let result = run_command(
+            Command::new("sh").args(["-c", "setsid sleep 30 & sleep 30"]),
+            Some(started + Duration::from_millis(200)),
+        );
+
+        assert!(matches!(
+            result.map_err(|error| error.kind().clone()),
+            Err(ErrorKind::Timeout { .. })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(15));
+    }
+}
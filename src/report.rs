@@ -0,0 +1,449 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::command::Crate;
+use crate::platform::compare_rust_versions;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestStatusEnum {
+    Passed,
+    Failed,
+    Flaky,
+    TimedOut,
+}
+
+impl fmt::Display for TestStatusEnum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestStatusEnum::Passed => f.pad("passed"),
+            TestStatusEnum::Failed => f.pad("failed"),
+            TestStatusEnum::Flaky => f.pad("flaky"),
+            TestStatusEnum::TimedOut => f.pad("timed out"),
+        }
+    }
+}
+
+// Where the sources of a tested crate were taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckoutEnum {
+    Cached,
+    Downloaded,
+    Reused,
+    Vendored,
+    Local,
+}
+
+// Compilation units built and reused (fresh) by a cargo command, parsed from
+// its verbose output, along with the time spent on the build.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuildStats {
+    compiled: usize,
+    reused: usize,
+    duration: Duration,
+}
+
+impl BuildStats {
+    pub fn parse(output: &str) -> Self {
+        let mut stats = BuildStats::default();
+        for line in output.lines().map(|line| line.trim_start()) {
+            if line.starts_with("Compiling ") {
+                stats.compiled += 1;
+            } else if line.starts_with("Fresh ") {
+                stats.reused += 1;
+            } else if line.starts_with("Finished ") {
+                stats.duration = line
+                    .rsplit_once(" in ")
+                    .map(|(_, duration)| parse_cargo_duration(duration))
+                    .unwrap_or_default();
+            }
+        }
+        stats
+    }
+
+    fn add(&mut self, other: &BuildStats) {
+        self.compiled += other.compiled;
+        self.reused += other.reused;
+        self.duration += other.duration;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestOutcome {
+    configuration: String,
+    status: TestStatusEnum,
+    output: String,
+    flaky_tests: Vec<String>,
+    build_stats: BuildStats,
+    command_line: String,
+    toolchain: Option<String>,
+}
+
+impl TestOutcome {
+    pub fn new(configuration: &str, status: TestStatusEnum, output: &str) -> Self {
+        TestOutcome {
+            configuration: configuration.to_string(),
+            status,
+            output: output.to_string(),
+            flaky_tests: Vec::new(),
+            build_stats: BuildStats::default(),
+            command_line: String::new(),
+            toolchain: None,
+        }
+    }
+
+    pub fn with_toolchain(mut self, toolchain: Option<String>) -> Self {
+        self.toolchain = toolchain;
+        self
+    }
+
+    pub fn with_command_line(mut self, command_line: &str) -> Self {
+        self.command_line = command_line.to_string();
+        self
+    }
+
+    pub fn with_build_stats(mut self, build_stats: BuildStats) -> Self {
+        self.build_stats = build_stats;
+        self
+    }
+
+    // Test cases that failed at first, but passed after rerunning.
+    pub fn with_flaky_tests(mut self, flaky_tests: Vec<String>) -> Self {
+        self.flaky_tests = flaky_tests;
+        self
+    }
+
+    fn is_failed(&self) -> bool {
+        self.status == TestStatusEnum::Failed || self.status == TestStatusEnum::TimedOut
+    }
+
+    // The output preceded by the command, that produced it.
+    fn get_command_output(&self) -> String {
+        format!("$ {}\n{}", self.command_line, self.output)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CrateReport {
+    used_crate: Crate,
+    checkout: Option<CheckoutEnum>,
+    warnings: Vec<String>,
+    outcomes: Vec<TestOutcome>,
+}
+
+impl CrateReport {
+    pub fn new(used_crate: &Crate) -> Self {
+        CrateReport {
+            used_crate: used_crate.clone(),
+            checkout: None,
+            warnings: Vec::new(),
+            outcomes: Vec::new(),
+        }
+    }
+
+    pub fn get_crate(&self) -> &Crate {
+        &self.used_crate
+    }
+
+    pub fn set_checkout(&mut self, checkout: CheckoutEnum) {
+        self.checkout = Some(checkout);
+    }
+
+    pub fn add_warning(&mut self, warning: &str) {
+        self.warnings.push(warning.to_string());
+    }
+
+    pub fn add_outcome(&mut self, outcome: TestOutcome) {
+        self.outcomes.push(outcome);
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.outcomes.iter().any(|outcome| outcome.is_failed())
+    }
+
+    pub fn get_flaky_tests(&self) -> Vec<String> {
+        let mut flaky_tests = Vec::new();
+        for test in self
+            .outcomes
+            .iter()
+            .flat_map(|outcome| outcome.flaky_tests.iter())
+        {
+            if !flaky_tests.contains(test) {
+                flaky_tests.push(test.clone());
+            }
+        }
+        flaky_tests
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.outcomes
+            .iter()
+            .any(|outcome| outcome.status == TestStatusEnum::TimedOut)
+    }
+
+    // The worst status among configurations tested with the toolchain.
+    pub fn get_toolchain_status(&self, toolchain: Option<&str>) -> Option<TestStatusEnum> {
+        let statuses = self
+            .outcomes
+            .iter()
+            .filter(|outcome| outcome.toolchain.as_deref() == toolchain)
+            .map(|outcome| outcome.status)
+            .collect::<Vec<TestStatusEnum>>();
+
+        [
+            TestStatusEnum::TimedOut,
+            TestStatusEnum::Failed,
+            TestStatusEnum::Flaky,
+            TestStatusEnum::Passed,
+        ]
+        .iter()
+        .find(|status| statuses.contains(status))
+        .cloned()
+    }
+
+    // Output of every failed configuration with its command line. The
+    // configuration names are mentioned only when the crate was tested in
+    // more than one of them.
+    pub fn get_failure_output(&self) -> String {
+        let failed_outcomes = self.outcomes.iter().filter(|outcome| outcome.is_failed());
+
+        match self.outcomes.len() {
+            1 => failed_outcomes
+                .map(|outcome| outcome.get_command_output())
+                .collect(),
+            _ => failed_outcomes
+                .map(|outcome| {
+                    format!(
+                        "--- {} ({}) ---\n{}",
+                        outcome.configuration,
+                        outcome.status,
+                        outcome.get_command_output()
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+        }
+    }
+}
+
+pub fn print_feature_matrix(reports: &[CrateReport]) {
+    println!("Test results:");
+    for report in reports.iter() {
+        println!("    {}", report.used_crate);
+        for outcome in report.outcomes.iter() {
+            println!("        {:<10}{}", outcome.status, outcome.configuration);
+        }
+    }
+}
+
+// Shows the status of each crate (in rows) for each toolchain (in columns),
+// followed by the crates, that pass on some toolchains and fail on others.
+// The default toolchain is `None`.
+pub fn print_toolchain_grid(reports: &[CrateReport], toolchains: &[Option<String>]) {
+    println!("Toolchains:");
+    let toolchain_names = toolchains
+        .iter()
+        .map(|toolchain| toolchain.as_deref().unwrap_or("default").to_string())
+        .collect::<Vec<String>>();
+    let header = toolchain_names
+        .iter()
+        .map(|toolchain| format!("{:<10}", toolchain))
+        .collect::<String>();
+    println!("    {}", header);
+
+    let mut regressions = Vec::new();
+    for report in reports.iter() {
+        let statuses = toolchains
+            .iter()
+            .map(|toolchain| report.get_toolchain_status(toolchain.as_deref()))
+            .collect::<Vec<Option<TestStatusEnum>>>();
+        let row = statuses
+            .iter()
+            .map(|status| match status {
+                Some(status) => format!("{:<10}", status),
+                None => format!("{:<10}", "-"),
+            })
+            .collect::<String>();
+        println!(
+            "    {}{} v{}",
+            row,
+            report.used_crate.get_name(),
+            report.used_crate.get_version()
+        );
+
+        let select_toolchains = |is_failed: bool| {
+            toolchain_names
+                .iter()
+                .zip(statuses.iter())
+                .filter(|(_, status)| match status {
+                    Some(TestStatusEnum::Failed) | Some(TestStatusEnum::TimedOut) => is_failed,
+                    Some(_) => !is_failed,
+                    None => false,
+                })
+                .map(|(toolchain, _)| toolchain.to_owned())
+                .collect::<Vec<String>>()
+        };
+        let passed = select_toolchains(false);
+        let failed = select_toolchains(true);
+        if !passed.is_empty() && !failed.is_empty() {
+            regressions.push(format!(
+                "{} v{} passes on {}, fails on {}",
+                report.used_crate.get_name(),
+                report.used_crate.get_version(),
+                passed.join(", "),
+                failed.join(", ")
+            ));
+        }
+    }
+
+    if !regressions.is_empty() {
+        println!("Regressions between toolchains:");
+        for regression in regressions.iter() {
+            println!("    {}", regression);
+        }
+    }
+}
+
+// Lists crates that declare a higher `rust-version` than the project, so
+// they can't be built with the project's minimal supported Rust version.
+pub fn print_msrv_violations(crates: &[Crate], rust_version: &str) {
+    let violations = crates
+        .iter()
+        .filter_map(|used_crate| {
+            used_crate
+                .get_rust_version()
+                .filter(|version| compare_rust_versions(version, rust_version).is_gt())
+                .map(|version| (used_crate, version))
+        })
+        .collect::<Vec<(&Crate, String)>>();
+
+    match violations.is_empty() {
+        true => println!(
+            "All crates support the minimal Rust version {}.",
+            rust_version
+        ),
+        false => {
+            println!(
+                "{} crates break the minimal Rust version {}:",
+                violations.len(),
+                rust_version
+            );
+            for (used_crate, version) in violations.iter() {
+                println!(
+                    "    {} v{} requires Rust {}",
+                    used_crate.get_name(),
+                    used_crate.get_version(),
+                    version
+                );
+            }
+        }
+    }
+}
+
+// Tells how many crates from registries and Git were reused from the local
+// Cargo cache or from the checkout cache of previous runs and how many of
+// them had to be downloaded.
+pub fn print_checkout_summary(reports: &[CrateReport]) {
+    let count = |checkout: CheckoutEnum| {
+        reports
+            .iter()
+            .filter(|report| report.checkout == Some(checkout))
+            .count()
+    };
+
+    let cached = count(CheckoutEnum::Cached);
+    let downloaded = count(CheckoutEnum::Downloaded);
+    let reused = count(CheckoutEnum::Reused);
+    let reused_message = match reused {
+        0 => String::new(),
+        _ => format!(" {} from the checkout cache,", reused),
+    };
+    if cached + downloaded + reused > 0 {
+        println!(
+            "Fetched {} crates: {} from the local cache,{} {} downloaded.",
+            cached + downloaded + reused,
+            cached,
+            reused_message,
+            downloaded
+        );
+    }
+}
+
+pub fn print_warnings(reports: &[CrateReport]) {
+    let warnings = reports
+        .iter()
+        .flat_map(|report| report.warnings.iter())
+        .collect::<Vec<&String>>();
+    if !warnings.is_empty() {
+        println!("Warnings:");
+        for warning in warnings {
+            println!("    {}", warning);
+        }
+    }
+}
+
+// Flaky crates aren't counted as failed (unless it's asked for), but they
+// are listed with the test cases that passed only after rerunning.
+pub fn print_flaky_crates(reports: &[CrateReport]) {
+    let flaky_reports = reports
+        .iter()
+        .filter(|report| !report.get_flaky_tests().is_empty())
+        .collect::<Vec<&CrateReport>>();
+    if !flaky_reports.is_empty() {
+        println!("Flaky {} crates:", flaky_reports.len());
+        for report in flaky_reports {
+            println!("    {}", report.used_crate);
+            for test in report.get_flaky_tests() {
+                println!("        {}", test);
+            }
+        }
+    }
+}
+
+// Estimates the time saved by the shared target directory: every reused unit
+// is counted as taking the average time of units compiled during the run.
+pub fn print_build_summary(reports: &[CrateReport]) {
+    let mut total = BuildStats::default();
+    reports
+        .iter()
+        .flat_map(|report| report.outcomes.iter())
+        .for_each(|outcome| total.add(&outcome.build_stats));
+
+    print!(
+        "Built {} units in {:.1}s, {} units were fresh",
+        total.compiled,
+        total.duration.as_secs_f64(),
+        total.reused
+    );
+    // Without compiled units there is nothing to estimate the build time by.
+    match total.compiled {
+        0 => println!("."),
+        compiled => {
+            let estimate = total.duration / compiled as u32 * total.reused as u32;
+            println!(
+                " (about {:.1}s at the average build time of a unit).",
+                estimate.as_secs_f64()
+            );
+        }
+    }
+    // Configurations of a crate reuse each other's units even in a target
+    // directory of their own, so not all of them are saved by sharing it.
+    println!(
+        "Fresh units include the ones reused between configurations of the same crate, \
+         so the time saved by sharing the target directory can be lower."
+    );
+}
+
+// Cargo prints build durations as `0.52s` or `1m 02s`.
+fn parse_cargo_duration(value: &str) -> Duration {
+    value
+        .split_whitespace()
+        .map(|part| {
+            let (amount, multiplier) = match part.strip_suffix('m') {
+                Some(minutes) => (minutes, 60.0),
+                None => (part.trim_end_matches('s'), 1.0),
+            };
+            amount.parse::<f64>().unwrap_or(0.0) * multiplier
+        })
+        .map(Duration::from_secs_f64)
+        .sum()
+}
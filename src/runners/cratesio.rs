@@ -1,52 +1,83 @@
-use std::env::{current_dir, set_current_dir};
 use std::fs::create_dir_all;
-use std::path::PathBuf;
-use std::process::Command;
-
-use failure::ResultExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::Instant;
 
+use crate::cache::{extract_archive, is_cached_checkout, RegistryCache};
 use crate::command::{Crate, DependencyTypeEnum};
 use crate::error::{Error, ErrorKind, Result};
+use crate::matrix::TestConfiguration;
+use crate::process::run_command;
+use crate::report::CheckoutEnum;
 use crate::runners::traits::TestRunner;
+use crate::util::copy_directory;
 
 pub struct CratesIoDependencyTestRunner {
     crate_name: String,
     version: String,
-    parent_directory: String,
+    registry_args: Vec<String>,
+    cached_archive: Option<PathBuf>,
+    cached_sources: Option<PathBuf>,
     target_directory: String,
 }
 
 impl TestRunner for CratesIoDependencyTestRunner {
-    fn new(dependency: &Crate) -> Self {
-        let version = match dependency.get_dependency_type() {
-            DependencyTypeEnum::CratesIo(value) => value.to_owned(),
-            _ => unreachable!(),
+    fn new(dependency: &Crate, deps_directory: &Path) -> Self {
+        // Crates from other registries are cloned by the registry name, when
+        // it is in Cargo config (so its credentials are used), or by the index.
+        let registry_args = match dependency.get_dependency_type() {
+            DependencyTypeEnum::Registry(options) => match options.get_name() {
+                Some(name) => vec![String::from("--registry"), name],
+                None => vec![String::from("--index"), options.get_index()],
+            },
+            _ => Vec::new(),
         };
 
-        let current_directory = current_dir().unwrap();
-        let parent_directory = current_directory.to_str().unwrap().to_string();
-        let target_directory = current_directory
-            .join(dependency.get_name().clone())
+        let cache = RegistryCache::load();
+        let cached_archive = cache.find_archive(dependency);
+        let cached_sources = cache.find_sources(dependency);
+
+        let target_directory = deps_directory
+            .join(dependency.get_checkout_name())
             .to_str()
             .unwrap()
             .to_string();
 
         CratesIoDependencyTestRunner {
             crate_name: dependency.get_name(),
-            version,
-            parent_directory,
+            version: dependency.get_version(),
+            registry_args,
+            cached_archive,
+            cached_sources,
             target_directory,
         }
     }
 
-    fn setup(&self) -> Result<()> {
+    fn get_crate_name(&self) -> String {
+        self.crate_name.clone()
+    }
+
+    fn setup(&mut self, deadline: Option<Instant>) -> Result<CheckoutEnum> {
+        if is_cached_checkout(Path::new(&self.target_directory)) {
+            return Ok(CheckoutEnum::Reused);
+        }
+
         let target_directory = self.target_directory.clone();
         create_dir_all(target_directory.clone())?;
 
-        let deps_directory = PathBuf::from(target_directory);
-        set_current_dir(deps_directory.parent().unwrap())?;
+        // The verified archive is preferred over the unpacked sources, which
+        // could have been edited in place, and both over downloading.
+        if let Some(archive) = &self.cached_archive {
+            extract_archive(archive, Path::new(&self.target_directory))?;
+            return Ok(CheckoutEnum::Cached);
+        }
+        if let Some(sources) = &self.cached_sources {
+            copy_directory(sources, Path::new(&self.target_directory))?;
+            return Ok(CheckoutEnum::Cached);
+        }
 
-        let mut command_args = Vec::new();
+        let deps_directory = PathBuf::from(target_directory);
+        let mut command_args = self.registry_args.clone();
 
         let crate_name_arg = format!("{0}@{1}", self.crate_name.clone(), self.version.clone());
         command_args.push(crate_name_arg);
@@ -54,19 +85,16 @@ impl TestRunner for CratesIoDependencyTestRunner {
         command_args.push("--".to_string());
         command_args.push(self.target_directory.clone());
 
-        let output = Command::new("cargo")
-            .arg("clone")
-            .args(&command_args)
-            .output()
-            .with_context(|err| ErrorKind::Io {
-                reason: format!("{}", err),
-            })?;
+        let output = run_command(
+            Command::new("cargo")
+                .arg("clone")
+                .args(&command_args)
+                .current_dir(deps_directory.parent().unwrap()),
+            deadline,
+        )?;
 
         match output.status.success() {
-            true => {
-                set_current_dir(self.target_directory.clone())?;
-                Ok(())
-            }
+            true => Ok(CheckoutEnum::Downloaded),
             false => Err(Error::from(ErrorKind::TestsFailure {
                 crate_name: self.crate_name.to_owned(),
                 output: String::from_utf8_lossy(&output.stderr).to_string(),
@@ -74,20 +102,31 @@ impl TestRunner for CratesIoDependencyTestRunner {
         }
     }
 
-    fn run_tests(&self) -> Result<()> {
-        let output = self.run_cargo_command("test")?;
+    fn get_checkout_directory(&self) -> Option<PathBuf> {
+        Some(PathBuf::from(&self.target_directory))
+    }
+
+    fn get_working_directory(&self) -> PathBuf {
+        PathBuf::from(&self.target_directory)
+    }
+
+    fn run_tests(
+        &self,
+        configuration: &TestConfiguration,
+        deadline: Option<Instant>,
+    ) -> Result<Output> {
+        let output = self.run_cargo_command(configuration, deadline)?;
 
         match output.status.success() {
-            true => Ok(()),
+            true => Ok(output),
             false => Err(Error::from(ErrorKind::TestsFailure {
                 crate_name: self.crate_name.to_owned(),
-                output: String::from_utf8_lossy(&output.stdout).to_string(),
+                output: self.get_failure_output(configuration, &output),
             })),
         }
     }
 
     fn teardown(&self) -> Result<()> {
-        set_current_dir(self.parent_directory.clone()).unwrap();
         Ok(())
     }
 }
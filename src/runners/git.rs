@@ -1,107 +1,226 @@
-use std::env::{current_dir, set_current_dir};
-use std::path::PathBuf;
-use std::process::Command;
-
-use failure::ResultExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::Instant;
 
+use crate::cache::is_cached_checkout;
 use crate::command::{Crate, DependencyTypeEnum, SourceOptions};
 use crate::error::{Error, ErrorKind, Result};
+use crate::matrix::TestConfiguration;
+use crate::process::run_command;
+use crate::report::CheckoutEnum;
 use crate::runners::traits::TestRunner;
+use crate::util::find_package_directory;
 
 pub struct GitDependencyTestRunner {
     crate_name: String,
+    version: String,
     url: String,
     source_options: SourceOptions,
-    parent_directory: String,
     target_directory: String,
+    package_directory: Option<PathBuf>,
+}
+
+impl GitDependencyTestRunner {
+    // The revision for checking out: the commit from Cargo.lock, when it is
+    // known, otherwise the reference from the manifest.
+    fn get_revision(&self) -> Option<String> {
+        self.source_options
+            .get_locked_commit()
+            .or_else(|| self.source_options.get_commit())
+            .or_else(|| {
+                self.source_options
+                    .get_tag()
+                    .map(|tag| format!("refs/tags/{}", tag))
+            })
+            .or_else(|| {
+                self.source_options
+                    .get_branch()
+                    .map(|branch| format!("origin/{}", branch))
+            })
+    }
+
+    fn run_git_command(&self, args: &[&str], deadline: Option<Instant>) -> Result<Output> {
+        let output = run_command(
+            Command::new("git")
+                .args(args)
+                .current_dir(&self.target_directory),
+            deadline,
+        )?;
+
+        match output.status.success() {
+            true => Ok(output),
+            false => Err(Error::from(ErrorKind::TestsFailure {
+                crate_name: self.crate_name.to_owned(),
+                output: String::from_utf8_lossy(&output.stderr).to_string(),
+            })),
+        }
+    }
+
+    fn clone_repository(&self, deadline: Option<Instant>) -> Result<()> {
+        let output = run_command(
+            Command::new("git")
+                .arg("clone")
+                .arg("--quiet")
+                .arg(&self.url)
+                .arg(&self.target_directory),
+            deadline,
+        )?;
+
+        if !output.status.success() {
+            return Err(Error::from(ErrorKind::TestsFailure {
+                crate_name: self.crate_name.to_owned(),
+                output: String::from_utf8_lossy(&output.stderr).to_string(),
+            }));
+        }
+
+        if let Some(revision) = self.get_revision() {
+            self.run_git_command(&["checkout", "--quiet", "--detach", &revision], deadline)?;
+        }
+
+        Ok(())
+    }
+
+    fn resolve_commit(&self, revision: &str, deadline: Option<Instant>) -> Result<String> {
+        let output = self.run_git_command(
+            &["rev-parse", &format!("{}^{{commit}}", revision)],
+            deadline,
+        )?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
 }
 
 impl TestRunner for GitDependencyTestRunner {
-    fn new(dependency: &Crate) -> Self {
+    fn new(dependency: &Crate, deps_directory: &Path) -> Self {
         let source_options = match dependency.get_dependency_type() {
             DependencyTypeEnum::Git(options) => options,
             _ => SourceOptions::default(),
         };
 
-        let current_directory = current_dir().unwrap();
-        let parent_directory = current_directory.to_str().unwrap().to_string();
-        let target_directory = current_directory
-            .join(dependency.get_name().clone())
+        let target_directory = deps_directory
+            .join(dependency.get_checkout_name())
             .to_str()
             .unwrap()
             .to_string();
 
         GitDependencyTestRunner {
             crate_name: dependency.get_name(),
+            version: dependency.get_version(),
             url: dependency.get_path(),
             source_options,
-            parent_directory,
             target_directory,
+            package_directory: None,
         }
     }
 
-    fn setup(&self) -> Result<()> {
-        let target_directory = self.target_directory.clone();
-        let deps_directory = PathBuf::from(target_directory);
-        set_current_dir(deps_directory.parent().unwrap())?;
-
-        let mut command_args = Vec::new();
+    fn get_crate_name(&self) -> String {
+        self.crate_name.clone()
+    }
 
-        command_args.push("--prefix".to_string());
-        command_args.push(self.target_directory.clone());
+    fn setup(&mut self, deadline: Option<Instant>) -> Result<CheckoutEnum> {
+        // Reused clones are only fetched to learn where the branches are now,
+        // which doesn't matter when the network isn't available.
+        let checkout = match is_cached_checkout(Path::new(&self.target_directory)) {
+            true => {
+                let _ = self.run_git_command(&["fetch", "--quiet", "origin"], deadline);
+                CheckoutEnum::Reused
+            }
+            false => {
+                self.clone_repository(deadline)?;
+                CheckoutEnum::Downloaded
+            }
+        };
 
-        if let Some(branch) = self.source_options.get_branch() {
-            command_args.push("--branch".to_string());
-            command_args.push(branch.clone());
+        // Makes sure that the tested sources are exactly the ones from the
+        // lockfile, because the commit could be an abbreviated or a moved ref.
+        if let Some(locked_commit) = self.source_options.get_locked_commit() {
+            let head = self.resolve_commit("HEAD", deadline)?;
+            if head != locked_commit {
+                return Err(Error::from(ErrorKind::RevisionMismatch {
+                    crate_name: self.crate_name.to_owned(),
+                    expected: locked_commit,
+                    actual: head,
+                }));
+            }
         }
 
-        if let Some(tag) = self.source_options.get_tag() {
-            command_args.push("--tag".to_string());
-            command_args.push(tag.clone());
+        // Repositories often contain a few crates, so tests are started from
+        // the directory of the package to use the workspace it belongs to.
+        let package_directory = find_package_directory(
+            Path::new(&self.target_directory),
+            &self.crate_name,
+            &self.version,
+        )
+        .ok_or_else(|| {
+            Error::from(ErrorKind::PackageNotFound {
+                crate_name: self.crate_name.to_owned(),
+                url: self.url.to_owned(),
+            })
+        })?;
+
+        self.package_directory = Some(package_directory);
+        Ok(checkout)
+    }
+
+    // Warns when the tracked branch (or the default one) has got new commits
+    // since the lockfile was updated, so the tested code isn't the latest.
+    fn get_warnings(&self) -> Vec<String> {
+        let locked_commit = match self.source_options.get_locked_commit() {
+            Some(commit) => commit,
+            None => return Vec::new(),
+        };
+        if self.source_options.get_tag().is_some() || self.source_options.get_commit().is_some() {
+            return Vec::new();
         }
 
-        if let Some(commit) = self.source_options.get_commit() {
-            command_args.push("--rev".to_string());
-            command_args.push(commit.clone());
+        let (reference, branch_name) = match self.source_options.get_branch() {
+            Some(branch) => (format!("origin/{}", branch), format!("`{}`", branch)),
+            None => (String::from("origin/HEAD"), String::from("default")),
+        };
+        match self.resolve_commit(&reference, None) {
+            Ok(head) if head != locked_commit => vec![format!(
+                "The {} branch of {} has moved past the locked commit {} (now at {}).",
+                branch_name,
+                self.url,
+                &locked_commit[..locked_commit.len().min(8)],
+                &head[..head.len().min(8)]
+            )],
+            _ => Vec::new(),
         }
+    }
 
-        command_args.push("--git".to_string());
-        command_args.push(self.url.clone());
+    fn get_checkout_directory(&self) -> Option<PathBuf> {
+        Some(PathBuf::from(&self.target_directory))
+    }
 
-        let output = Command::new("cargo")
-            .arg("clone")
-            .args(&command_args)
-            .output()
-            .with_context(|err| ErrorKind::Io {
-                reason: format!("{}", err),
-            })?;
+    fn get_working_directory(&self) -> PathBuf {
+        self.package_directory
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(&self.target_directory))
+    }
 
-        match output.status.success() {
-            true => {
-                set_current_dir(self.target_directory.clone())?;
-                Ok(())
-            }
-            false => Err(Error::from(ErrorKind::TestsFailure {
-                crate_name: self.crate_name.to_owned(),
-                output: String::from_utf8_lossy(&output.stderr).to_string(),
-            })),
-        }
+    fn get_cargo_args(&self, configuration: &TestConfiguration) -> Vec<String> {
+        let mut args = vec![String::from("-p"), self.crate_name.clone()];
+        args.extend(configuration.get_cargo_args());
+        args
     }
 
-    fn run_tests(&self) -> Result<()> {
-        let output = self.run_cargo_command("test")?;
+    fn run_tests(
+        &self,
+        configuration: &TestConfiguration,
+        deadline: Option<Instant>,
+    ) -> Result<Output> {
+        let output = self.run_cargo_command(configuration, deadline)?;
 
         match output.status.success() {
-            true => Ok(()),
+            true => Ok(output),
             false => Err(Error::from(ErrorKind::TestsFailure {
                 crate_name: self.crate_name.to_owned(),
-                output: String::from_utf8_lossy(&output.stdout).to_string(),
+                output: self.get_failure_output(configuration, &output),
             })),
         }
     }
 
     fn teardown(&self) -> Result<()> {
-        set_current_dir(self.parent_directory.clone()).unwrap();
         Ok(())
     }
 }
@@ -1,7 +1,12 @@
-use std::env::set_current_dir;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::time::Instant;
 
 use crate::command::Crate;
 use crate::error::{Error, ErrorKind, Result};
+use crate::matrix::TestConfiguration;
+use crate::patch::Patches;
+use crate::report::CheckoutEnum;
 use crate::runners::traits::TestRunner;
 
 pub struct LocalDependencyTestRunner {
@@ -10,26 +15,57 @@ pub struct LocalDependencyTestRunner {
 }
 
 impl TestRunner for LocalDependencyTestRunner {
-    fn new(dependency: &Crate) -> Self {
+    fn new(dependency: &Crate, _deps_directory: &Path) -> Self {
         LocalDependencyTestRunner {
             crate_name: dependency.get_name(),
             sources_directory: dependency.get_path(),
         }
     }
 
-    fn setup(&self) -> Result<()> {
-        set_current_dir(self.sources_directory.clone())?;
+    fn get_crate_name(&self) -> String {
+        self.crate_name.clone()
+    }
+
+    fn setup(&mut self, _deadline: Option<Instant>) -> Result<CheckoutEnum> {
+        Ok(CheckoutEnum::Local)
+    }
+
+    fn get_working_directory(&self) -> PathBuf {
+        PathBuf::from(&self.sources_directory)
+    }
+
+    // Local crates are tested in place, so their own lockfile is never
+    // replaced or updated, and their workspace isn't even looked for.
+    fn get_checkout_workspace_location(&self) -> Result<PathBuf> {
+        Ok(self.get_working_directory())
+    }
+
+    fn use_lockfile(&self, _lockfile: &Path, _workspace_location: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn update_patched_packages(
+        &self,
+        _patches: &Patches,
+        _workspace_location: &Path,
+        _configuration: &TestConfiguration,
+        _deadline: Option<Instant>,
+    ) -> Result<()> {
         Ok(())
     }
 
-    fn run_tests(&self) -> Result<()> {
-        let output = self.run_cargo_command("test")?;
+    fn run_tests(
+        &self,
+        configuration: &TestConfiguration,
+        deadline: Option<Instant>,
+    ) -> Result<Output> {
+        let output = self.run_cargo_command(configuration, deadline)?;
 
         match output.status.success() {
-            true => Ok(()),
+            true => Ok(output),
             false => Err(Error::from(ErrorKind::TestsFailure {
                 crate_name: self.crate_name.to_owned(),
-                output: String::from_utf8_lossy(&output.stdout).to_string(),
+                output: self.get_failure_output(configuration, &output),
             })),
         }
     }
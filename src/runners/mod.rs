@@ -2,18 +2,35 @@ mod cratesio;
 mod git;
 mod local;
 mod traits;
+mod vendored;
 
 pub use crate::runners::cratesio::CratesIoDependencyTestRunner;
 pub use crate::runners::git::GitDependencyTestRunner;
 pub use crate::runners::local::LocalDependencyTestRunner;
 pub use crate::runners::traits::TestRunner;
+pub use crate::runners::vendored::VendoredDependencyTestRunner;
+
+use std::path::Path;
 
 use crate::command::{Crate, DependencyTypeEnum};
 
-pub fn get_test_runner(dependency: &Crate) -> Box<dyn TestRunner> {
+pub fn get_test_runner(dependency: &Crate, deps_directory: &Path) -> Box<dyn TestRunner> {
+    if dependency.get_vendored_path().is_some() {
+        return Box::new(VendoredDependencyTestRunner::new(
+            dependency,
+            deps_directory,
+        ));
+    }
+
     match dependency.get_dependency_type() {
-        DependencyTypeEnum::CratesIo(_) => Box::new(CratesIoDependencyTestRunner::new(dependency)),
-        DependencyTypeEnum::Git(_) => Box::new(GitDependencyTestRunner::new(dependency)),
-        DependencyTypeEnum::Local => Box::new(LocalDependencyTestRunner::new(dependency)),
+        DependencyTypeEnum::CratesIo | DependencyTypeEnum::Registry(_) => Box::new(
+            CratesIoDependencyTestRunner::new(dependency, deps_directory),
+        ),
+        DependencyTypeEnum::Git(_) => {
+            Box::new(GitDependencyTestRunner::new(dependency, deps_directory))
+        }
+        DependencyTypeEnum::Local => {
+            Box::new(LocalDependencyTestRunner::new(dependency, deps_directory))
+        }
     }
 }
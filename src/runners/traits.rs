@@ -1,29 +1,207 @@
+use std::fs::{canonicalize, copy};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Output};
-
-use failure::ResultExt;
+use std::time::Instant;
 
 use crate::command::Crate;
-use crate::error::{ErrorKind, Result};
+use crate::error::{Error, ErrorKind, Result};
+use crate::lockfile::Lockfile;
+use crate::matrix::TestConfiguration;
+use crate::patch::Patches;
+use crate::process::run_command;
+use crate::report::CheckoutEnum;
+use crate::util::get_workspace_location;
 
 pub trait TestRunner {
-    fn new(dependency: &Crate) -> Self
+    // Runners are executed concurrently, so each of them works only in its
+    // own directory inside of `deps_directory` and never changes the working
+    // directory of the process.
+    fn new(dependency: &Crate, deps_directory: &Path) -> Self
     where
         Self: Sized;
 
-    fn setup(&self) -> Result<()>;
+    fn get_crate_name(&self) -> String;
+
+    // Prepares sources of the crate and tells where they were taken from.
+    // Commands still running on the deadline are stopped with an error.
+    fn setup(&mut self, deadline: Option<Instant>) -> Result<CheckoutEnum>;
+
+    // The directory with the copy of the sources, that belongs only to this
+    // runner. Downloaded ones can be stored in the checkout cache. Crates
+    // tested in place don't have it.
+    fn get_checkout_directory(&self) -> Option<PathBuf> {
+        None
+    }
 
-    fn run_tests(&self) -> Result<()>;
+    // The directory where cargo commands for the crate are started.
+    fn get_working_directory(&self) -> PathBuf;
+
+    // Non-fatal problems with the prepared sources, shown in the summary.
+    fn get_warnings(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Arguments of the cargo command that runs the configuration.
+    fn get_cargo_args(&self, configuration: &TestConfiguration) -> Vec<String> {
+        configuration.get_cargo_args()
+    }
+
+    // The full command for the configuration, recorded in the report, so a
+    // result can be reproduced by hand.
+    fn get_command_line(&self, configuration: &TestConfiguration) -> String {
+        configuration
+            .get_envs()
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .chain(vec![String::from("cargo")])
+            .chain(self.get_command_args(configuration))
+            .map(|arg| quote_shell_arg(&arg))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    // Arguments of `cargo`: the toolchain, when it is set, the command and
+    // its arguments.
+    fn get_command_args(&self, configuration: &TestConfiguration) -> Vec<String> {
+        let mut args = configuration
+            .get_toolchain()
+            .map(|toolchain| vec![format!("+{}", toolchain)])
+            .unwrap_or_default();
+        args.push(configuration.get_command());
+        args.extend(self.get_cargo_args(configuration));
+        args
+    }
+
+    // The workspace of the crate, where its Cargo.lock is changed. Cargo also
+    // looks for workspaces in parent directories, which could find the tested
+    // project around the checkout, so only a workspace inside of the checkout
+    // is returned.
+    fn get_checkout_workspace_location(&self) -> Result<PathBuf> {
+        let workspace_location = get_workspace_location(&self.get_working_directory())?;
+        let checkout_directory = self
+            .get_checkout_directory()
+            .unwrap_or_else(|| self.get_working_directory());
+        match canonicalize(&workspace_location)?.starts_with(canonicalize(&checkout_directory)?) {
+            true => Ok(workspace_location),
+            false => Err(Error::from(ErrorKind::WorkspaceOutsideCheckout {
+                workspace: workspace_location.to_string_lossy().to_string(),
+                checkout: checkout_directory.to_string_lossy().to_string(),
+            })),
+        }
+    }
+
+    // Puts the project's lockfile into the workspace of the crate. Cargo keeps
+    // the locked versions of packages shared with the project and resolves
+    // only the rest of them, like dev-dependencies of the crate.
+    fn use_lockfile(&self, lockfile: &Path, workspace_location: &Path) -> Result<()> {
+        copy(lockfile, workspace_location.join("Cargo.lock"))?;
+        Ok(())
+    }
+
+    // Cargo keeps the versions locked by the crate itself instead of using
+    // the injected patches, so the patched packages are updated first, with
+    // the toolchain of the configuration, since lockfile formats differ
+    // between Cargo versions. Packages that the crate doesn't use are skipped.
+    fn update_patched_packages(
+        &self,
+        patches: &Patches,
+        workspace_location: &Path,
+        configuration: &TestConfiguration,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        if !workspace_location.join("Cargo.lock").is_file() {
+            return Ok(());
+        }
+
+        let lockfile = Lockfile::load(workspace_location)?;
+        let toolchain_args = configuration
+            .get_toolchain()
+            .map(|toolchain| vec![format!("+{}", toolchain)])
+            .unwrap_or_default();
+        for name in patches.get_names() {
+            let specs = lockfile.get_package_specs(&name);
+            if specs.is_empty() {
+                continue;
+            }
+
+            let output = run_command(
+                Command::new("cargo")
+                    .args(&toolchain_args)
+                    .arg("update")
+                    .args(specs.iter().flat_map(|spec| ["--package", spec]))
+                    .args(patches.get_config_args())
+                    .current_dir(workspace_location),
+                deadline,
+            )?;
+            if !output.status.success() {
+                return Err(Error::from(ErrorKind::TestsFailure {
+                    crate_name: self.get_crate_name(),
+                    output: format!(
+                        "Can't update the patched {} package in Cargo.lock.\n{}",
+                        name,
+                        String::from_utf8_lossy(&output.stderr)
+                    ),
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    // Commands still running on the deadline are stopped with an error.
+    fn run_tests(
+        &self,
+        configuration: &TestConfiguration,
+        deadline: Option<Instant>,
+    ) -> Result<Output>;
 
     fn teardown(&self) -> Result<()>;
 
-    fn run_cargo_command(&self, command: &str) -> Result<Output> {
-        let output = Command::new("cargo")
-            .arg(command)
-            .output()
-            .with_context(|err| ErrorKind::Io {
-                reason: format!("{}", err),
-            })?;
+    // Failed test cases are described in stdout, followed by the errors that
+    // Cargo reports to stderr for every failed test binary, including the
+    // crashed ones. Commands that only build the crate, as well as tests that
+    // couldn't be built (for example, with an older toolchain), report errors
+    // to stderr only.
+    fn get_failure_output(&self, configuration: &TestConfiguration, output: &Output) -> String {
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        match configuration.get_mode().is_running_tests() && !output.stdout.is_empty() {
+            true => {
+                let errors = stderr
+                    .lines()
+                    .skip_while(|line| !line.starts_with("error"))
+                    .collect::<Vec<&str>>();
+                format!(
+                    "{}{}",
+                    String::from_utf8_lossy(&output.stdout),
+                    errors.join("\n")
+                )
+            }
+            false => stderr,
+        }
+    }
+
+    fn run_cargo_command(
+        &self,
+        configuration: &TestConfiguration,
+        deadline: Option<Instant>,
+    ) -> Result<Output> {
+        run_command(
+            Command::new("cargo")
+                .args(self.get_command_args(configuration))
+                .envs(configuration.get_envs())
+                .current_dir(self.get_working_directory()),
+            deadline,
+        )
+    }
+}
 
-        Ok(output)
+// Quotes the argument for a POSIX shell, when it contains special symbols.
+fn quote_shell_arg(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|symbol| symbol.is_ascii_alphanumeric() || "-_=+.,:/@%".contains(symbol));
+    match is_plain {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', "'\\''")),
     }
 }
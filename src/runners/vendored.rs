@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::time::Instant;
+
+use crate::command::Crate;
+use crate::error::{Error, ErrorKind, Result};
+use crate::matrix::TestConfiguration;
+use crate::report::CheckoutEnum;
+use crate::runners::traits::TestRunner;
+use crate::util::copy_directory;
+use crate::vendor::verify_vendored_crate;
+
+pub struct VendoredDependencyTestRunner {
+    crate_name: String,
+    checksum: Option<String>,
+    vendored_directory: String,
+    target_directory: String,
+}
+
+impl TestRunner for VendoredDependencyTestRunner {
+    fn new(dependency: &Crate, deps_directory: &Path) -> Self {
+        let target_directory = deps_directory
+            .join(dependency.get_checkout_name())
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        VendoredDependencyTestRunner {
+            crate_name: dependency.get_name(),
+            checksum: dependency.get_checksum(),
+            vendored_directory: dependency.get_vendored_path().unwrap_or_default(),
+            target_directory,
+        }
+    }
+
+    fn get_crate_name(&self) -> String {
+        self.crate_name.clone()
+    }
+
+    fn setup(&mut self, _deadline: Option<Instant>) -> Result<CheckoutEnum> {
+        let vendored_directory = Path::new(&self.vendored_directory);
+        verify_vendored_crate(
+            &self.crate_name,
+            vendored_directory,
+            self.checksum.as_deref(),
+        )?;
+
+        // Tests write build artifacts and sometimes files next to the sources,
+        // so the vendored copy is never used directly.
+        copy_directory(vendored_directory, Path::new(&self.target_directory))?;
+        Ok(CheckoutEnum::Vendored)
+    }
+
+    fn get_checkout_directory(&self) -> Option<PathBuf> {
+        Some(PathBuf::from(&self.target_directory))
+    }
+
+    fn get_working_directory(&self) -> PathBuf {
+        PathBuf::from(&self.target_directory)
+    }
+
+    fn run_tests(
+        &self,
+        configuration: &TestConfiguration,
+        deadline: Option<Instant>,
+    ) -> Result<Output> {
+        let output = self.run_cargo_command(configuration, deadline)?;
+
+        match output.status.success() {
+            true => Ok(output),
+            false => Err(Error::from(ErrorKind::TestsFailure {
+                crate_name: self.crate_name.to_owned(),
+                output: self.get_failure_output(configuration, &output),
+            })),
+        }
+    }
+
+    fn teardown(&self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+use std::str::FromStr;
+
+use cargo::util::lev_distance;
+use glob::Pattern;
+use semver::{Version, VersionReq};
+
+use crate::command::{Crate, DependencyTypeEnum};
+
+#[derive(Debug, Clone)]
+pub struct CrateSelector {
+    value: String,
+    name: Pattern,
+    version: Option<VersionReq>,
+}
+
+impl CrateSelector {
+    // Renamed dependencies can be selected by the package name as well as by
+    // the name from Cargo.toml.
+    pub fn matches(&self, used_crate: &Crate) -> bool {
+        let is_name_matched = self.name.matches(&used_crate.get_name())
+            || used_crate
+                .get_renames()
+                .iter()
+                .any(|rename| self.name.matches(rename));
+
+        is_name_matched
+            && match &self.version {
+                Some(version_req) => Version::parse(&used_crate.get_version())
+                    .map(|version| version_req.matches(&version))
+                    .unwrap_or(false),
+                None => true,
+            }
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.as_str().to_string()
+    }
+
+    pub fn is_pattern(&self) -> bool {
+        self.name.as_str().contains(|c| "*?[]".contains(c))
+    }
+
+    // Names of the known crates that look similar to the selector, so a typo
+    // in the name can be fixed without looking through the whole graph.
+    pub fn get_close_matches(&self, names: &[String]) -> Vec<String> {
+        let name = self.get_name();
+        let mut matches = names
+            .iter()
+            .map(|known_name| (lev_distance(&name, known_name), known_name))
+            .filter(|(distance, known_name)| *distance <= 3 && *distance < known_name.len())
+            .collect::<Vec<(usize, &String)>>();
+        matches.sort();
+        matches.dedup_by(|(_, left), (_, right)| left == right);
+        matches
+            .into_iter()
+            .map(|(_, known_name)| known_name.to_owned())
+            .collect()
+    }
+}
+
+impl FromStr for CrateSelector {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let (name, version) = match value.split_once('@') {
+            Some((name, version)) => (name, Some(version)),
+            None => (value, None),
+        };
+
+        let name = Pattern::new(name)
+            .map_err(|err| format!("Invalid crate name pattern `{}`: {}", name, err))?;
+        let version = match version {
+            Some(version) => Some(
+                VersionReq::parse(version)
+                    .map_err(|err| format!("Invalid version `{}`: {}", version, err))?,
+            ),
+            None => None,
+        };
+
+        Ok(CrateSelector {
+            value: value.to_string(),
+            name,
+            version,
+        })
+    }
+}
+
+impl fmt::Display for CrateSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKindEnum {
+    Registry,
+    Git,
+    Path,
+}
+
+impl SourceKindEnum {
+    pub fn matches(&self, used_crate: &Crate) -> bool {
+        matches!(
+            (self, used_crate.get_dependency_type()),
+            (SourceKindEnum::Registry, DependencyTypeEnum::CratesIo)
+                | (SourceKindEnum::Registry, DependencyTypeEnum::Registry(_))
+                | (SourceKindEnum::Git, DependencyTypeEnum::Git(_))
+                | (SourceKindEnum::Path, DependencyTypeEnum::Local)
+        )
+    }
+}
+
+impl FromStr for SourceKindEnum {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "registry" => Ok(SourceKindEnum::Registry),
+            "git" => Ok(SourceKindEnum::Git),
+            "path" => Ok(SourceKindEnum::Path),
+            _ => Err(format!(
+                "Expected `registry`, `git` or `path`, but got `{}`.",
+                value
+            )),
+        }
+    }
+}
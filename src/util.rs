@@ -1,3 +1,4 @@
+use std::fs::{copy, create_dir_all, read_dir, read_to_string};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -6,6 +7,7 @@ use cargo::util::toml::read_manifest;
 use cargo::Config;
 use failure::ResultExt;
 use rustc_serialize::json::Json;
+use toml::Value;
 
 use crate::error::{Error, ErrorKind, Result};
 
@@ -39,12 +41,29 @@ pub fn load_cargo_toml(path: &Path) -> Result<Manifest> {
 }
 
 pub fn get_project_location() -> Result<PathBuf> {
-    let output = Command::new("cargo")
-        .arg("locate-project")
-        .output()
-        .context(ErrorKind::InvalidCommand {
-            description: String::from("Can't execute the `cargo locate-project` command."),
-        })?;
+    locate_project(Command::new("cargo").arg("locate-project"))
+}
+
+// The root directory of the workspace, that the package in the directory
+// belongs to. It is the package directory itself without a workspace.
+pub fn get_workspace_location(directory: &Path) -> Result<PathBuf> {
+    locate_project(
+        Command::new("cargo")
+            .arg("locate-project")
+            .arg("--workspace")
+            .current_dir(directory),
+    )
+}
+
+fn locate_project(command: &mut Command) -> Result<PathBuf> {
+    let output = command.output().context(ErrorKind::InvalidCommand {
+        description: String::from("Can't execute the `cargo locate-project` command."),
+    })?;
+    if !output.status.success() {
+        return Err(Error::from(ErrorKind::InvalidCommand {
+            description: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }));
+    }
 
     let response =
         String::from_utf8(output.stdout.clone()).with_context(|err| ErrorKind::Utf8 {
@@ -64,3 +83,78 @@ pub fn get_project_location() -> Result<PathBuf> {
 
     Ok(project_location.to_path_buf())
 }
+
+pub fn copy_directory(source: &Path, destination: &Path) -> Result<()> {
+    create_dir_all(destination)?;
+    for entry in read_dir(source)? {
+        let entry = entry?;
+        let path = entry.path();
+        let target = destination.join(entry.file_name());
+        match entry.file_type()?.is_dir() {
+            true => copy_directory(&path, &target)?,
+            false => {
+                copy(&path, &target)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Looks for the manifest of the package in a repository with a few crates.
+// The version is compared only when the manifest has it as a plain string,
+// since it can be inherited from the workspace. Shallower manifests win, so
+// that copies of the package in test fixtures are not picked up.
+pub fn find_package_directory(root: &Path, name: &str, version: &str) -> Option<PathBuf> {
+    let mut candidates = Vec::new();
+    collect_package_directories(root, name, &mut candidates);
+    candidates.sort_by_key(|(directory, _)| directory.components().count());
+
+    let matches_version = |package_version: &Option<String>| match package_version {
+        Some(package_version) => package_version == version,
+        None => true,
+    };
+    candidates
+        .iter()
+        .find(|(_, package_version)| matches_version(package_version))
+        .map(|(directory, _)| directory.clone())
+}
+
+fn collect_package_directories(
+    directory: &Path,
+    name: &str,
+    candidates: &mut Vec<(PathBuf, Option<String>)>,
+) {
+    let manifest = read_to_string(directory.join("Cargo.toml"))
+        .ok()
+        .and_then(|content| content.parse::<Value>().ok());
+    if let Some(package) = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.get("package"))
+    {
+        if package.get("name").and_then(|value| value.as_str()) == Some(name) {
+            let version = package
+                .get("version")
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string());
+            candidates.push((directory.to_path_buf(), version));
+        }
+    }
+
+    let entries = match read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let file_name = entry.file_name();
+        let is_skipped = file_name == ".git" || file_name == "target";
+        // Symbolic links aren't followed, since they can point to their own
+        // parents.
+        let is_directory = entry
+            .file_type()
+            .map(|file_type| file_type.is_dir())
+            .unwrap_or(false);
+        if is_directory && !is_skipped {
+            collect_package_directories(&entry.path(), name, candidates);
+        }
+    }
+}
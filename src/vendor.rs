@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fs::{read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+
+use cargo::core::Shell;
+use cargo::util::{homedir, ConfigValue};
+use cargo::Config;
+use cargo_util::Sha256;
+use failure::ResultExt;
+use rustc_serialize::json::Json;
+use toml::Value;
+
+use crate::command::{Crate, DependencyTypeEnum};
+use crate::error::{Error, ErrorKind, Result};
+
+// The name of the source that Cargo uses for crates.io in `[source]` tables.
+const CRATES_IO_SOURCE: &str = "crates-io";
+
+// Source replacement may be chained (`crates-io` -> `mirror` -> `vendored`),
+// so following it is limited to protect against misconfigured cycles.
+const MAX_REPLACEMENTS: usize = 16;
+
+#[derive(Debug, Clone, Default)]
+struct SourceDefinition {
+    replace_with: Option<String>,
+    directory: Option<PathBuf>,
+    registry: Option<String>,
+    git: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VendoredSources {
+    sources: HashMap<String, SourceDefinition>,
+}
+
+impl VendoredSources {
+    // Reads `[source]` tables from the Cargo config of the project, the same
+    // way as Cargo does for the build, so that project-level replacements in
+    // `.cargo/config.toml` are taken into account.
+    pub fn load(project_location: &Path) -> Result<Self> {
+        let config = load_config(project_location)?;
+        let values = config.values().map_err(|err| {
+            Error::from(ErrorKind::Other {
+                description: format!("Can't read the Cargo config. Reason: {}", err),
+            })
+        })?;
+
+        let mut sources = HashMap::new();
+        if let Some(ConfigValue::Table(table, _)) = values.get("source") {
+            for (name, value) in table.iter() {
+                if let ConfigValue::Table(fields, _) = value {
+                    sources.insert(name.clone(), parse_source_definition(fields, &config));
+                }
+            }
+        }
+
+        Ok(VendoredSources { sources })
+    }
+
+    // Returns the directory of the vendored copy of the crate. Only sources
+    // that are eventually replaced by a `directory` source are supported.
+    pub fn find_crate(&self, used_crate: &Crate) -> Option<PathBuf> {
+        let directory = self.get_replacement_directory(used_crate)?;
+        read_dir(directory)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| is_vendored_package(path, used_crate))
+    }
+
+    fn get_replacement_directory(&self, used_crate: &Crate) -> Option<&PathBuf> {
+        let mut name = self.get_source_name(used_crate)?;
+        for _ in 0..MAX_REPLACEMENTS {
+            let source = self.sources.get(&name)?;
+            match (&source.replace_with, &source.directory) {
+                (Some(replace_with), _) => name = replace_with.clone(),
+                (None, Some(directory)) => return Some(directory),
+                (None, None) => return None,
+            }
+        }
+        None
+    }
+
+    fn get_source_name(&self, used_crate: &Crate) -> Option<String> {
+        let url = used_crate.get_path();
+        let url = url.trim_end_matches('/');
+        let find_by_url = |source_url: fn(&SourceDefinition) -> Option<&String>| {
+            self.sources
+                .iter()
+                .find(|(_, source)| {
+                    source_url(source)
+                        .map(|value| value.trim_end_matches('/') == url)
+                        .unwrap_or(false)
+                })
+                .map(|(name, _)| name.clone())
+        };
+
+        match used_crate.get_dependency_type() {
+            DependencyTypeEnum::CratesIo => Some(String::from(CRATES_IO_SOURCE)),
+            DependencyTypeEnum::Registry(options) => options
+                .get_name()
+                .filter(|name| self.sources.contains_key(name))
+                .or_else(|| find_by_url(|source| source.registry.as_ref())),
+            DependencyTypeEnum::Git(_) => find_by_url(|source| source.git.as_ref()),
+            DependencyTypeEnum::Local => None,
+        }
+    }
+}
+
+// Checks the vendored files against `.cargo-checksum.json`, generated by
+// `cargo vendor`, and the package checksum against the one from Cargo.lock.
+pub fn verify_vendored_crate(
+    crate_name: &str,
+    directory: &Path,
+    checksum: Option<&str>,
+) -> Result<()> {
+    let checksum_path = directory.join(".cargo-checksum.json");
+    let content = read_to_string(&checksum_path)?;
+    let json = Json::from_str(&content).context(ErrorKind::Other {
+        description: format!("Can't parse the {} file.", checksum_path.display()),
+    })?;
+
+    let package_checksum = json.find("package").and_then(|value| value.as_string());
+    if let (Some(expected), Some(actual)) = (checksum, package_checksum) {
+        if expected != actual {
+            return Err(Error::from(ErrorKind::ChecksumMismatch {
+                crate_name: crate_name.to_string(),
+                path: checksum_path.display().to_string(),
+            }));
+        }
+    }
+
+    let files = json
+        .find("files")
+        .and_then(|files| files.as_object())
+        .cloned()
+        .unwrap_or_default();
+    for (file, expected) in files.iter() {
+        let path = directory.join(file);
+        let actual = Sha256::new()
+            .update_path(&path)
+            .map(|hasher| hasher.finish_hex())
+            .unwrap_or_default();
+        if Some(actual.as_str()) != expected.as_string() {
+            return Err(Error::from(ErrorKind::ChecksumMismatch {
+                crate_name: crate_name.to_string(),
+                path: path.display().to_string(),
+            }));
+        }
+    }
+
+    Ok(())
+}
+
+fn load_config(project_location: &Path) -> Result<Config> {
+    let cwd = project_location.to_path_buf();
+    let home = homedir(&cwd).ok_or_else(|| {
+        Error::from(ErrorKind::Other {
+            description: String::from("Can't find the Cargo home directory."),
+        })
+    })?;
+    Ok(Config::new(Shell::new(), cwd, home))
+}
+
+fn parse_source_definition(
+    fields: &HashMap<String, ConfigValue>,
+    config: &Config,
+) -> SourceDefinition {
+    let get_string = |key: &str| match fields.get(key) {
+        Some(ConfigValue::String(value, _)) => Some(value.clone()),
+        _ => None,
+    };
+
+    // Relative directories are resolved against the directory that contains
+    // the `.cargo` folder with the config file, as Cargo does.
+    let directory = match fields.get("directory") {
+        Some(ConfigValue::String(value, definition)) => Some(definition.root(config).join(value)),
+        _ => None,
+    };
+
+    SourceDefinition {
+        replace_with: get_string("replace-with"),
+        directory,
+        registry: get_string("registry"),
+        git: get_string("git"),
+    }
+}
+
+fn is_vendored_package(path: &Path, used_crate: &Crate) -> bool {
+    let manifest = match read_to_string(path.join("Cargo.toml")) {
+        Ok(content) => content.parse::<Value>().ok(),
+        Err(_) => None,
+    };
+
+    manifest
+        .as_ref()
+        .and_then(|manifest| manifest.get("package"))
+        .map(|package| {
+            let name = package.get("name").and_then(|value| value.as_str());
+            let version = package.get("version").and_then(|value| value.as_str());
+            name == Some(used_crate.get_name().as_str())
+                && version == Some(used_crate.get_version().as_str())
+        })
+        .unwrap_or(false)
+}
@@ -1,13 +1,606 @@
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::time::Instant;
+
+use crate::cache::CheckoutCache;
 use crate::command::Crate;
-use crate::error::Result;
-use crate::runners::get_test_runner;
+use crate::error::{Error, ErrorKind, Result};
+use crate::matrix::TestConfiguration;
+use crate::patch::Patches;
+use crate::process::{is_expired, TimeLimits};
+use crate::report::{BuildStats, CheckoutEnum, CrateReport, TestOutcome, TestStatusEnum};
+use crate::runners::{get_test_runner, TestRunner};
+
+// How checkouts of crates are prepared before testing.
+#[derive(Debug, Clone, Default)]
+pub struct CheckoutOptions {
+    pub cache: Option<CheckoutCache>,
+    pub lockfile: Option<PathBuf>,
+    pub patches: Option<Patches>,
+}
+
+pub fn run_crate_tests(
+    used_crate: Crate,
+    configurations: Vec<TestConfiguration>,
+    deps_directory: &Path,
+    time_limits: TimeLimits,
+    retries: usize,
+    checkout_options: CheckoutOptions,
+) -> Result<CrateReport> {
+    let deadline = time_limits.get_crate_deadline(Instant::now());
+    let mut test_runner = get_test_runner(&used_crate, deps_directory);
+    let mut report = CrateReport::new(&used_crate);
+
+    // Crates that aren't started before the deadline aren't even checked out.
+    let timeout_error = |output: &str| {
+        Error::from(ErrorKind::TestsTimeout {
+            crate_name: used_crate.get_name(),
+            output: output.to_string(),
+        })
+    };
+    if is_expired(deadline) {
+        return Err(timeout_error(""));
+    }
 
-pub fn run_crate_tests(used_crate: Crate) -> Result<Crate> {
-    let test_runner = get_test_runner(&used_crate);
+    // Downloaded checkouts are cached before anything in them is changed.
+    let checkout_directory = test_runner.get_checkout_directory();
+    if let (Some(cache), Some(directory)) = (&checkout_options.cache, &checkout_directory) {
+        cache.restore(&used_crate, directory)?;
+    }
+    let map_timeout = |error: Error| match error.kind() {
+        ErrorKind::Timeout { output } => timeout_error(output),
+        _ => error,
+    };
+    let checkout = test_runner.setup(deadline).map_err(map_timeout)?;
+    report.set_checkout(checkout);
+    if let (Some(cache), Some(directory), CheckoutEnum::Downloaded) =
+        (&checkout_options.cache, &checkout_directory, checkout)
+    {
+        cache.store(&used_crate, directory)?;
+    }
 
-    test_runner.setup()?;
-    test_runner.run_tests()?;
+    let warning = prepare_lockfile(
+        test_runner.as_ref(),
+        &checkout_options,
+        configurations.first(),
+        deadline,
+    )
+    .map_err(map_timeout)?;
+    if let Some(warning) = warning {
+        report.add_warning(&warning);
+    }
+    for warning in test_runner.get_warnings() {
+        report.add_warning(&warning);
+    }
+    for configuration in configurations.iter() {
+        let configuration = &match retries > 0 {
+            true => configuration.with_no_fail_fast(),
+            false => configuration.clone(),
+        };
+        let name = configuration.get_name();
+        let command_line = test_runner.get_command_line(configuration);
+        let outcome = match run_tests(test_runner.as_ref(), configuration, deadline) {
+            Ok(output) => TestOutcome::new(&name, TestStatusEnum::Passed, "")
+                .with_build_stats(BuildStats::parse(&String::from_utf8_lossy(&output.stderr))),
+            Err(error) => match error.kind() {
+                ErrorKind::TestsFailure { output, .. } => rerun_failed_tests(
+                    test_runner.as_ref(),
+                    configuration,
+                    deadline,
+                    retries,
+                    output,
+                ),
+                ErrorKind::Timeout { output } => {
+                    TestOutcome::new(&name, TestStatusEnum::TimedOut, output)
+                }
+                _ => return Err(error),
+            },
+        };
+        report.add_outcome(
+            outcome
+                .with_command_line(&command_line)
+                .with_toolchain(configuration.get_toolchain()),
+        );
+    }
     test_runner.teardown()?;
 
-    Ok(used_crate)
+    Ok(report)
+}
+
+// Applies the project's lockfile and patches to the workspace of the checkout.
+// When Cargo can't locate the workspace, or it's outside of the checkout, the
+// crate is tested with its own lockfile and the reason is returned as a
+// warning.
+fn prepare_lockfile(
+    test_runner: &dyn TestRunner,
+    checkout_options: &CheckoutOptions,
+    configuration: Option<&TestConfiguration>,
+    deadline: Option<Instant>,
+) -> Result<Option<String>> {
+    if checkout_options.lockfile.is_none() && checkout_options.patches.is_none() {
+        return Ok(None);
+    }
+    let workspace_location = match test_runner.get_checkout_workspace_location() {
+        Ok(workspace_location) => workspace_location,
+        Err(error) => {
+            return Ok(Some(format!(
+                "The lockfile of the crate is left as is. {}",
+                error
+            )))
+        }
+    };
+
+    if let Some(lockfile) = &checkout_options.lockfile {
+        test_runner.use_lockfile(lockfile, &workspace_location)?;
+    }
+    if let (Some(patches), Some(configuration)) = (&checkout_options.patches, configuration) {
+        test_runner.update_patched_packages(
+            patches,
+            &workspace_location,
+            configuration,
+            deadline,
+        )?;
+    }
+    Ok(None)
+}
+
+fn run_tests(
+    test_runner: &dyn TestRunner,
+    configuration: &TestConfiguration,
+    deadline: Option<Instant>,
+) -> Result<Output> {
+    // Once the time is over, the rest of configurations isn't started.
+    match is_expired(deadline) {
+        true => Err(Error::from(ErrorKind::Timeout {
+            output: String::new(),
+        })),
+        false => test_runner.run_tests(configuration, deadline),
+    }
+}
+
+// Reruns only the failed test cases up to `retries` times. Test cases that
+// pass on some attempt are flaky, and the configuration is flaky when all
+// failed test cases are. Failures that aren't explained by failed test cases
+// in the output (like compilation errors or crashed test binaries) are never
+// rerun.
+fn rerun_failed_tests(
+    test_runner: &dyn TestRunner,
+    configuration: &TestConfiguration,
+    deadline: Option<Instant>,
+    retries: usize,
+    output: &str,
+) -> TestOutcome {
+    let name = configuration.get_name();
+    let mut failed_tests = match has_unexplained_failures(output) {
+        true => Vec::new(),
+        false => parse_failed_tests(output),
+    };
+    let mut flaky_tests = Vec::new();
+
+    for _ in 0..retries {
+        if failed_tests.is_empty() {
+            break;
+        }
+
+        let filtered_configuration = configuration.with_test_filters(&failed_tests);
+        let still_failed_tests = match run_tests(test_runner, &filtered_configuration, deadline) {
+            Ok(_) => Vec::new(),
+            Err(error) => match error.kind() {
+                ErrorKind::TestsFailure { output, .. } if !has_unexplained_failures(output) => {
+                    match parse_failed_tests(output) {
+                        tests if tests.is_empty() => break,
+                        tests => tests,
+                    }
+                }
+                ErrorKind::Timeout { output } => {
+                    return TestOutcome::new(&name, TestStatusEnum::TimedOut, output)
+                        .with_flaky_tests(flaky_tests);
+                }
+                _ => break,
+            },
+        };
+
+        let (still_failed, passed): (Vec<String>, Vec<String>) = failed_tests
+            .into_iter()
+            .partition(|test| still_failed_tests.contains(test));
+        flaky_tests.extend(passed);
+        failed_tests = still_failed;
+    }
+
+    match failed_tests.is_empty() && !flaky_tests.is_empty() {
+        true => TestOutcome::new(&name, TestStatusEnum::Flaky, output),
+        false => TestOutcome::new(&name, TestStatusEnum::Failed, output),
+    }
+    .with_flaky_tests(flaky_tests)
+}
+
+// Collects names of the failed test cases from the libtest output, where
+// they are printed as `test <name> ... FAILED`.
+fn parse_failed_tests(output: &str) -> Vec<String> {
+    let mut tests = Vec::new();
+    for line in output.lines() {
+        let test = line
+            .strip_prefix("test ")
+            .and_then(|line| line.strip_suffix(" ... FAILED"))
+            .map(|test| test.to_string());
+        if let Some(test) = test {
+            if !tests.contains(&test) {
+                tests.push(test);
+            }
+        }
+    }
+    tests
+}
+
+// Checks whether some test binaries failed without reporting their failed
+// test cases: libtest prints a summary for every binary that runs to the end,
+// and Cargo reports an error for every failed one.
+fn has_unexplained_failures(output: &str) -> bool {
+    let lines = output.lines().collect::<Vec<&str>>();
+    let started_binaries = lines
+        .iter()
+        .filter(|line| {
+            line.starts_with("running ") && (line.ends_with(" test") || line.ends_with(" tests"))
+        })
+        .count();
+    let finished_binaries = lines
+        .iter()
+        .filter(|line| line.starts_with("test result: "))
+        .count();
+    let failed_summaries = lines
+        .iter()
+        .filter(|line| line.starts_with("test result: FAILED."))
+        .collect::<Vec<&&str>>();
+    let failed_binaries = lines
+        .iter()
+        .filter(|line| {
+            line.starts_with("error: test failed") || line.starts_with("error: doctest failed")
+        })
+        .count();
+    let failed_tests = failed_summaries
+        .iter()
+        .flat_map(|line| line.split("; "))
+        .filter_map(|part| part.trim().trim_end_matches(';').strip_suffix(" failed"))
+        .filter_map(|count| count.rsplit(' ').next()?.parse::<usize>().ok())
+        .sum::<usize>();
+    let named_tests = lines
+        .iter()
+        .filter(|line| line.starts_with("test ") && line.ends_with(" ... FAILED"))
+        .count();
+
+    started_binaries > finished_binaries
+        || failed_binaries > failed_summaries.len()
+        || failed_tests > named_tests
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::process::{self, ExitStatus};
+
+    use cargo::core::{Dependency, SourceId};
+
+    use super::*;
+    use crate::report::CheckoutEnum;
+
+    // Replays the given outputs of test runs: `None` for a passed run and
+    // the libtest output for a failed one. The crate is in the `package`
+    // directory of the checkout.
+    struct ReplayTestRunner {
+        outputs: RefCell<Vec<Option<String>>>,
+        runs: RefCell<Vec<Vec<String>>>,
+        checkout_directory: PathBuf,
+        package: String,
+    }
+
+    impl ReplayTestRunner {
+        fn with_outputs(outputs: Vec<Option<&str>>) -> Self {
+            ReplayTestRunner {
+                outputs: RefCell::new(
+                    outputs
+                        .into_iter()
+                        .rev()
+                        .map(|output| output.map(|output| output.to_string()))
+                        .collect(),
+                ),
+                runs: RefCell::new(Vec::new()),
+                checkout_directory: PathBuf::new(),
+                package: String::new(),
+            }
+        }
+
+        fn with_checkout(checkout_directory: &Path, package: &str) -> Self {
+            ReplayTestRunner {
+                checkout_directory: checkout_directory.to_path_buf(),
+                package: package.to_string(),
+                ..ReplayTestRunner::with_outputs(Vec::new())
+            }
+        }
+    }
+
+    impl TestRunner for ReplayTestRunner {
+        fn new(_dependency: &Crate, _deps_directory: &Path) -> Self {
+            ReplayTestRunner::with_outputs(Vec::new())
+        }
+
+        fn get_crate_name(&self) -> String {
+            String::from("replay")
+        }
+
+        fn setup(&mut self, _deadline: Option<Instant>) -> Result<CheckoutEnum> {
+            Ok(CheckoutEnum::Local)
+        }
+
+        fn get_checkout_directory(&self) -> Option<PathBuf> {
+            Some(self.checkout_directory.clone())
+        }
+
+        fn get_working_directory(&self) -> PathBuf {
+            self.checkout_directory.join(&self.package)
+        }
+
+        fn run_tests(
+            &self,
+            configuration: &TestConfiguration,
+            _deadline: Option<Instant>,
+        ) -> Result<Output> {
+            self.runs.borrow_mut().push(configuration.get_cargo_args());
+            match self.outputs.borrow_mut().pop().flatten() {
+                None => Ok(Output {
+                    status: ExitStatus::default(),
+                    stdout: Vec::new(),
+                    stderr: Vec::new(),
+                }),
+                Some(output) => Err(Error::from(ErrorKind::TestsFailure {
+                    crate_name: String::from("replay"),
+                    output,
+                })),
+            }
+        }
+
+        fn teardown(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // Reruns the failed tests and reports the outcome for a local crate.
+    fn rerun(test_runner: &ReplayTestRunner, retries: usize, output: &str) -> CrateReport {
+        let configuration = TestConfiguration::new("default", Vec::new()).with_no_fail_fast();
+        let outcome = rerun_failed_tests(test_runner, &configuration, None, retries, output);
+
+        let source_id = SourceId::for_path(Path::new("/replay")).unwrap();
+        let dependency = Dependency::parse("replay", None, source_id).unwrap();
+        let mut report = CrateReport::new(&Crate::new(&dependency, "0.1.0", None, None));
+        report.add_outcome(outcome);
+        report
+    }
+
+    const FAILED_OUTPUT: &str = "running 3 tests
+test tests::first ... ok
+test tests::second ... FAILED
+test tests::third ... FAILED
+
+failures:
+    tests::second
+    tests::third
+
+test result: FAILED. 1 passed; 2 failed; 0 ignored; 0 measured
+";
+
+    #[test]
+    fn parse_failed_tests_collects_failed_names() {
+        assert_eq!(
+            parse_failed_tests(FAILED_OUTPUT),
+            vec![String::from("tests::second"), String::from("tests::third")]
+        );
+    }
+
+    #[test]
+    fn parse_failed_tests_skips_duplicates_and_other_lines() {
+        let output = "test a ... FAILED\ntest a ... FAILED\ntest b ... ignored\nerror: test failed";
+        assert_eq!(parse_failed_tests(output), vec![String::from("a")]);
+        assert!(parse_failed_tests("error[E0425]: cannot find value").is_empty());
+    }
+
+    #[test]
+    fn tests_passed_on_rerun_are_flaky() {
+        let test_runner = ReplayTestRunner::with_outputs(vec![None]);
+        let report = rerun(&test_runner, 2, FAILED_OUTPUT);
+
+        assert_eq!(
+            report.get_toolchain_status(None),
+            Some(TestStatusEnum::Flaky)
+        );
+        assert_eq!(
+            report.get_flaky_tests(),
+            vec![String::from("tests::second"), String::from("tests::third")]
+        );
+        assert_eq!(
+            test_runner.runs.borrow().as_slice(),
+            &[vec![
+                String::from("--no-fail-fast"),
+                String::from("--"),
+                String::from("--exact"),
+                String::from("tests::second"),
+                String::from("tests::third"),
+            ]]
+        );
+    }
+
+    #[test]
+    fn tests_failing_on_every_rerun_fail_the_configuration() {
+        let test_runner = ReplayTestRunner::with_outputs(vec![
+            Some("test tests::third ... FAILED"),
+            Some("test tests::third ... FAILED"),
+        ]);
+        let report = rerun(&test_runner, 2, FAILED_OUTPUT);
+
+        assert_eq!(
+            report.get_toolchain_status(None),
+            Some(TestStatusEnum::Failed)
+        );
+        assert_eq!(
+            report.get_flaky_tests(),
+            vec![String::from("tests::second")]
+        );
+        assert_eq!(test_runner.runs.borrow().len(), 2);
+    }
+
+    #[test]
+    fn failures_without_failed_tests_are_not_rerun() {
+        let test_runner = ReplayTestRunner::with_outputs(vec![None]);
+        let report = rerun(&test_runner, 2, "error[E0425]: cannot find value");
+
+        assert_eq!(
+            report.get_toolchain_status(None),
+            Some(TestStatusEnum::Failed)
+        );
+        assert!(test_runner.runs.borrow().is_empty());
+    }
+
+    #[test]
+    fn failures_are_not_rerun_without_retries() {
+        let test_runner = ReplayTestRunner::with_outputs(vec![None]);
+        let report = rerun(&test_runner, 0, FAILED_OUTPUT);
+
+        assert_eq!(
+            report.get_toolchain_status(None),
+            Some(TestStatusEnum::Failed)
+        );
+        assert!(test_runner.runs.borrow().is_empty());
+    }
+
+    #[test]
+    fn crashed_test_binaries_are_not_flaky() {
+        // The library tests report their failed case, while the integration
+        // tests crash before printing a summary.
+        let output = "running 2 tests
+test tests::first ... ok
+test tests::second ... FAILED
+
+failures:
+    tests::second
+
+test result: FAILED. 1 passed; 1 failed; 0 ignored; 0 measured
+
+running 3 tests
+test works ... ok
+error: test failed, to rerun pass `--lib`
+error: test failed, to rerun pass `--test integration`
+
+Caused by:
+  process didn't exit successfully: `integration-0123` (signal: 11, SIGSEGV)
+";
+        let test_runner = ReplayTestRunner::with_outputs(vec![None]);
+        let report = rerun(&test_runner, 2, output);
+
+        assert_eq!(
+            report.get_toolchain_status(None),
+            Some(TestStatusEnum::Failed)
+        );
+        assert!(report.get_flaky_tests().is_empty());
+        assert!(test_runner.runs.borrow().is_empty());
+    }
+
+    #[test]
+    fn failures_without_summaries_are_unexplained() {
+        assert!(!has_unexplained_failures(FAILED_OUTPUT));
+        assert!(has_unexplained_failures(
+            "running 1 test\ntest a ... ok\nerror: test failed, to rerun pass `--lib`"
+        ));
+        assert!(has_unexplained_failures(
+            "running 2 tests\ntest a ... FAILED\ntest result: FAILED. 0 passed; 2 failed;"
+        ));
+    }
+
+    // Creates a directory with the given manifests, like `("Cargo.toml",
+    // "[workspace]")`, where every package gets an empty library.
+    fn create_fixture(name: &str, manifests: &[(&str, &str)]) -> PathBuf {
+        let root = temp_dir().join(format!("cargo-test-all-worker-{}-{}", process::id(), name));
+        for (path, manifest) in manifests {
+            let directory = root.join(path).parent().unwrap().to_path_buf();
+            create_dir_all(directory.join("src")).unwrap();
+            write(directory.join("src/lib.rs"), "").unwrap();
+            write(root.join(path), manifest).unwrap();
+        }
+        write(root.join("project.lock"), "# The project's lockfile.\n").unwrap();
+        root
+    }
+
+    fn get_lockfile_options(root: &Path) -> CheckoutOptions {
+        CheckoutOptions {
+            lockfile: Some(root.join("project.lock")),
+            ..CheckoutOptions::default()
+        }
+    }
+
+    const PACKAGE: &str = "[package]\nname = \"member\"\nversion = \"0.1.0\"\n";
+
+    #[test]
+    fn project_lockfile_is_copied_into_workspace_of_checkout() {
+        let root = create_fixture(
+            "copy",
+            &[
+                (
+                    "checkout/Cargo.toml",
+                    "[workspace]\nmembers = [\"member\"]\n",
+                ),
+                ("checkout/member/Cargo.toml", PACKAGE),
+            ],
+        );
+        let test_runner = ReplayTestRunner::with_checkout(&root.join("checkout"), "member");
+        let warning = prepare_lockfile(&test_runner, &get_lockfile_options(&root), None, None);
+
+        assert!(warning.unwrap().is_none());
+        assert_eq!(
+            read_to_string(root.join("checkout/Cargo.lock")).unwrap(),
+            "# The project's lockfile.\n"
+        );
+        assert!(!root.join("checkout/member/Cargo.lock").exists());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn workspaces_outside_of_checkout_are_left_as_is() {
+        let root = create_fixture(
+            "outside",
+            &[
+                ("Cargo.toml", "[workspace]\nmembers = [\"checkout\"]\n"),
+                ("checkout/Cargo.toml", PACKAGE),
+            ],
+        );
+        let test_runner = ReplayTestRunner::with_checkout(&root.join("checkout"), "");
+        let warning = prepare_lockfile(&test_runner, &get_lockfile_options(&root), None, None);
+
+        assert!(warning
+            .unwrap()
+            .unwrap()
+            .contains("is outside of the checkout"));
+        assert!(!root.join("Cargo.lock").exists());
+        assert!(!root.join("checkout/Cargo.lock").exists());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn workspaces_rejected_by_cargo_are_left_as_is() {
+        // The parent workspace doesn't list the checkout as its member.
+        let root = create_fixture(
+            "rejected",
+            &[
+                ("Cargo.toml", "[workspace]\nmembers = []\n"),
+                ("checkout/Cargo.toml", PACKAGE),
+            ],
+        );
+        let test_runner = ReplayTestRunner::with_checkout(&root.join("checkout"), "");
+        let warning = prepare_lockfile(&test_runner, &get_lockfile_options(&root), None, None);
+
+        assert!(warning
+            .unwrap()
+            .unwrap()
+            .contains("believes it's in a workspace"));
+        assert!(!root.join("checkout/Cargo.lock").exists());
+        remove_dir_all(&root).unwrap();
+    }
 }
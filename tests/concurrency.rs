@@ -0,0 +1,179 @@
+use std::env::{current_dir, temp_dir};
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use cargo_test_all::command::{CrateList, DependencyKindEnum, DepthEnum, TestOptions};
+use cargo_test_all::matrix::{get_test_configurations, PassThroughArgs, TestModeEnum};
+use cargo_test_all::process::TimeLimits;
+use cargo_test_all::worker::{run_crate_tests, CheckoutOptions};
+
+const DEPENDENCIES: usize = 6;
+
+static FIXTURE_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn get_test_options() -> TestOptions {
+    TestOptions {
+        threads: DEPENDENCIES,
+        test_only: Vec::new(),
+        exclude: Vec::new(),
+        sources: Vec::new(),
+        depth: DepthEnum::Limited(1),
+        packages: Vec::new(),
+        kinds: vec![DependencyKindEnum::Normal],
+        target: None,
+        features: Vec::new(),
+        all_features: false,
+        no_default_features: false,
+        feature_matrix: None,
+        matrix_limit: 32,
+        modes: vec![TestModeEnum::Test],
+        offline: false,
+        timeout: None,
+        deadline: None,
+        retries: 0,
+        strict_flaky: false,
+        shared_target: false,
+        build_cache: None,
+        args: PassThroughArgs::default(),
+        crate_args: Vec::new(),
+        toolchains: Vec::new(),
+        msrv: false,
+        msrv_test: false,
+        integration_lock: false,
+        inject_patches: false,
+        cache_directory: None,
+        keep: false,
+        keep_failed: false,
+    }
+}
+
+// Creates a project with a few local dependencies, where tests of the odd
+// ones fail. Running tests of a crate in a wrong directory flips the result.
+fn create_project() -> PathBuf {
+    let fixture_id = FIXTURE_ID.fetch_add(1, Ordering::SeqCst);
+    let root = temp_dir().join(format!(
+        "cargo-test-all-concurrency-{}-{}",
+        process::id(),
+        fixture_id
+    ));
+    let project = root.join("project");
+    create_dir_all(project.join("src")).unwrap();
+    write(project.join("src/lib.rs"), "").unwrap();
+
+    let mut dependencies = String::new();
+    for index in 0..DEPENDENCIES {
+        let name = format!("dep_{}", index);
+        let directory = root.join(&name);
+        create_dir_all(directory.join("src")).unwrap();
+        write(
+            directory.join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+                name
+            ),
+        )
+        .unwrap();
+        write(
+            directory.join("src/lib.rs"),
+            format!(
+                "#[test]\nfn is_even() {{\n    assert_eq!({} % 2, 0);\n}}\n",
+                index
+            ),
+        )
+        .unwrap();
+        dependencies.push_str(&format!("{} = {{ path = \"../{}\" }}\n", name, name));
+    }
+
+    write(
+        project.join("Cargo.toml"),
+        format!(
+            "[package]\nname = \"project\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+             [dependencies]\n{}",
+            dependencies
+        ),
+    )
+    .unwrap();
+
+    project
+}
+
+fn remove_project(project: &Path) {
+    remove_dir_all(project.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn concurrent_runners_test_their_own_crates() {
+    let project = create_project();
+    let options = get_test_options();
+    let crate_list = CrateList::load(&project, &options).unwrap();
+    let deps_directory = project.join("target/testing/deps");
+    create_dir_all(&deps_directory).unwrap();
+
+    let handles = crate_list
+        .get_tested_crates_list()
+        .iter()
+        .cloned()
+        .map(|used_crate| {
+            let deps_directory = deps_directory.clone();
+            thread::spawn(move || {
+                let configurations = get_test_configurations(&used_crate, None, 32);
+                run_crate_tests(
+                    used_crate,
+                    configurations,
+                    &deps_directory,
+                    TimeLimits::default(),
+                    0,
+                    CheckoutOptions::default(),
+                )
+                .unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut reports = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect::<Vec<_>>();
+    reports.sort_by_key(|report| report.get_crate().get_name());
+
+    assert_eq!(reports.len(), DEPENDENCIES);
+    for (index, report) in reports.iter().enumerate() {
+        assert_eq!(report.get_crate().get_name(), format!("dep_{}", index));
+        assert_eq!(report.is_failed(), index % 2 == 1);
+    }
+
+    remove_project(&project);
+}
+
+#[test]
+fn concurrent_runners_keep_process_directory() {
+    let directory = current_dir().unwrap();
+    let project = create_project();
+    let options = get_test_options();
+    let crate_list = CrateList::load(&project, &options).unwrap();
+    let deps_directory = project.join("target/testing/deps");
+
+    thread::scope(|scope| {
+        for used_crate in crate_list.get_tested_crates_list().iter().cloned() {
+            let deps_directory = &deps_directory;
+            scope.spawn(move || {
+                let configurations = get_test_configurations(&used_crate, None, 32);
+                run_crate_tests(
+                    used_crate,
+                    configurations,
+                    deps_directory,
+                    TimeLimits::default(),
+                    0,
+                    CheckoutOptions::default(),
+                )
+                .unwrap();
+            });
+        }
+    });
+
+    assert_eq!(current_dir().unwrap(), directory);
+    remove_project(&project);
+}
@@ -0,0 +1,75 @@
+use std::env::temp_dir;
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+// Creates a Git repository with a crate that has a passing test.
+fn create_repository(root: &Path) -> PathBuf {
+    let repository = root.join("dependency");
+    create_dir_all(repository.join("src")).unwrap();
+    write(
+        repository.join("Cargo.toml"),
+        "[package]\nname = \"dependency\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+    )
+    .unwrap();
+    write(
+        repository.join("src/lib.rs"),
+        "#[test]\nfn works() {\n    assert_eq!(2 + 2, 4);\n}\n",
+    )
+    .unwrap();
+
+    for args in [
+        vec!["init", "--quiet"],
+        vec!["add", "."],
+        vec!["commit", "--quiet", "--message", "Initial commit"],
+    ] {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(&args)
+            .current_dir(&repository)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    repository
+}
+
+// Creates a project whose root manifest has a `[workspace]` table and which
+// depends on the crate from the repository.
+fn create_project(root: &Path, repository: &Path) -> PathBuf {
+    let project = root.join("project");
+    create_dir_all(project.join("src")).unwrap();
+    write(project.join("src/lib.rs"), "").unwrap();
+    write(
+        project.join("Cargo.toml"),
+        format!(
+            "[package]\nname = \"project\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n\
+             [workspace]\n\n\
+             [dependencies]\ndependency = {{ git = \"file://{}\" }}\n",
+            repository.display()
+        ),
+    )
+    .unwrap();
+    project
+}
+
+#[test]
+fn checkouts_are_tested_outside_of_project_workspace() {
+    let root = temp_dir().join(format!("cargo-test-all-workspace-{}", process::id()));
+    let repository = create_repository(&root);
+    let project = create_project(&root, &repository);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-test-all"))
+        .current_dir(&project)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Well done! All crates work correctly."),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    remove_dir_all(&root).unwrap();
+}